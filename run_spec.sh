@@ -6,5 +6,6 @@ set -e
 export AUREUM_TEST_BASH="${SHELL:-bash}" # Use the same shell that is executing this file
 export AUREUM_TEST_EXEC="$PWD/target/debug/aureum"
 export AUREUM_TEST_HELLO_WORLD="Hello world" # Required by `basic/read_env_var.au.toml`
+export AUREUM_NO_STATE=true # Nested runs must not store their results in the spec directories
 
 cargo run -- "${@:-spec}"
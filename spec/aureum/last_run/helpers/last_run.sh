@@ -0,0 +1,51 @@
+#!/usr/bin/env bash
+# Runs the tests several times in a temporary directory, so that the results
+# are stored in a separate `.aureum/last-run.json`
+
+set -e
+unset AUREUM_NO_STATE
+
+dir=$(mktemp -d)
+cp helpers/tests.toml "$dir"
+cd "$dir"
+
+run() {
+    echo "\$ aureum $*"
+    "$AUREUM_TEST_EXEC" "$@" 2>&1 || true
+    echo
+}
+
+run --failed tests.toml # No results are stored yet
+run tests.toml # Not stored without `aureum.toml`
+ls -A
+echo
+
+run --failed-first tests.toml
+cat .aureum/last-run.json
+echo
+
+touch aureum.toml
+run tests.toml
+cat .aureum/last-run.json
+echo
+
+run --failed-first --show-all-tests tests.toml
+
+sed -i.bak 's/false/true/' tests.toml # All tests pass
+run --failed --show-all-tests tests.toml
+cat .aureum/last-run.json
+echo
+
+sed -i.bak 's/true/false/' tests.toml # All tests fail, but only the first is run
+run --fail-fast tests.toml
+cat .aureum/last-run.json
+echo
+
+echo "{" > .aureum/last-run.json
+run tests.toml
+run --no-state tests.toml
+cat .aureum/last-run.json # Not replaced
+AUREUM_NO_STATE=true run --failed tests.toml
+
+cd /
+rm -r "$dir"
@@ -0,0 +1,52 @@
+#!/bin/sh
+# Usage: watch.sh <path to aureum>
+#
+# Changes files while `aureum --watch` is running in a temporary directory, and
+# prints the output afterwards (with the escape character replaced by `^`)
+
+set -e
+
+dir=$(mktemp -d)
+cd "$dir"
+
+printf 'Hello\n' > expected.txt
+cat > test.au.toml <<'END'
+program = "echo"
+program_arguments = ["Hello"]
+
+[tests.from_file]
+expected_stdout = { file = "expected.txt" }
+
+[tests.literal]
+expected_stdout = "Hello\n"
+END
+
+# Waits until aureum has finished the given number of runs
+wait_for_runs() {
+  attempts=0
+  until [ "$(grep -c Watching stderr.txt)" -ge "$1" ]; do
+    attempts=$((attempts + 1))
+    if [ "$attempts" -gt 100 ]; then
+      echo "Timed out waiting for run $1" >&2
+      kill "$pid"
+      exit 1
+    fi
+    sleep 0.1
+  done
+}
+
+"$1" --watch test.au.toml > stdout.txt 2> stderr.txt &
+pid=$!
+
+wait_for_runs 1
+printf 'Goodbye\n' > expected.txt # Only `from_file` is affected
+wait_for_runs 2
+printf '\n[tests.added]\nexpected_exit_code = 0\n' >> test.au.toml # Only the new test is affected
+wait_for_runs 3
+kill "$pid"
+
+tr '\033' '^' < stdout.txt
+cat stderr.txt >&2
+
+cd /
+rm -r "$dir"
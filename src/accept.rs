@@ -0,0 +1,60 @@
+use crate::test_case::TestCase;
+use crate::test_result::{TestResult, ValueComparison};
+use std::fs;
+use std::io;
+
+/// Update the expectations of a test case to match the actual output
+///
+/// Only expected directories and expected output read from files are
+/// updated. Returns `true` if any files were changed.
+pub fn accept_test_result(test_case: &TestCase, test_result: &TestResult) -> io::Result<bool> {
+    let mut any_changes = false;
+
+    // The output is written after filters have been applied
+    for (step, step_result) in test_case.steps.iter().zip(&test_result.steps) {
+        let Some(step_result) = step_result else {
+            continue;
+        };
+
+        for (expected_file, comparison) in [
+            (&step.expected_stdout_file, &step_result.stdout),
+            (&step.expected_stderr_file, &step_result.stderr),
+        ] {
+            if let (Some(expected_file), ValueComparison::Diff { expected: _, got }) =
+                (expected_file, comparison)
+            {
+                fs::write(expected_file.to_logical_path("."), got)?;
+                any_changes = true;
+            }
+        }
+    }
+
+    if let (Some(expected_dir), Some(dir)) = (&test_case.expected_dir, &test_result.dir) {
+        let expected_path = expected_dir.to_logical_path(".");
+
+        for (path, comparison) in &dir.files {
+            if let ValueComparison::Diff { expected: _, got } = comparison {
+                let file_path = expected_dir.join(path).to_logical_path(".");
+
+                match got {
+                    Some(content) => {
+                        if let Some(parent) = file_path.parent() {
+                            fs::create_dir_all(parent)?;
+                        }
+                        fs::write(&file_path, content)?;
+                    }
+                    None => {
+                        fs::remove_file(&file_path)?;
+                    }
+                }
+
+                any_changes = true;
+            }
+        }
+
+        // Create the directory even if the program did not produce any files
+        fs::create_dir_all(expected_path)?;
+    }
+
+    Ok(any_changes)
+}
@@ -0,0 +1,57 @@
+//! Stop the running programs early, e.g. when using `--fail-fast`
+
+use crate::utils::process;
+use std::collections::BTreeMap;
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Keeps track of the running programs, so that they can be killed when the
+/// test run is cancelled
+#[derive(Default)]
+pub struct Cancellation {
+    is_cancelled: AtomicBool,
+    children: Mutex<BTreeMap<u32, Child>>, // Indexed by process ID
+}
+
+impl Cancellation {
+    pub fn new() -> Cancellation {
+        Self::default()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.is_cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Kill all running programs, including the processes they started. Programs
+    /// registered afterwards are killed immediately.
+    pub fn cancel(&self) {
+        let mut children = self.children.lock().expect("Lock should not be poisoned");
+        self.is_cancelled.store(true, Ordering::SeqCst);
+
+        for child in children.values_mut() {
+            process::kill_process_group(child);
+        }
+    }
+
+    /// The child is killed if the run is cancelled before it is unregistered. It
+    /// must be the leader of a process group (See `process::new_process_group`).
+    pub(crate) fn register(&self, mut child: Child) -> u32 {
+        let mut children = self.children.lock().expect("Lock should not be poisoned");
+        if self.is_cancelled() {
+            process::kill_process_group(&mut child);
+        }
+
+        let id = child.id();
+        children.insert(id, child);
+        id
+    }
+
+    pub(crate) fn unregister(&self, id: u32) -> Child {
+        self.children
+            .lock()
+            .expect("Lock should not be poisoned")
+            .remove(&id)
+            .expect("Child should be registered")
+    }
+}
@@ -1,8 +1,11 @@
+use aureum::convert::SourceFormat;
 use aureum::test_id::TestId;
 use aureum::test_id_container::TestIdContainer;
+use aureum::toml_config::ConfigFormat;
 use glob;
 use relative_path::RelativePathBuf;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -15,24 +18,25 @@ pub enum TestPath {
     },
 }
 
+/// Fails with the path that could not be searched
 pub fn expand_test_paths(
     test_paths: &[TestPath],
     current_dir: &Path,
-) -> BTreeMap<RelativePathBuf, TestIdContainer> {
+    search_markdown: bool,
+) -> Result<BTreeMap<RelativePathBuf, TestIdContainer>, (String, LocateFileError)> {
     let mut files = BTreeMap::new();
 
     for test_path in test_paths {
         match test_path {
             TestPath::Pipe => {} // Skip
             TestPath::Glob(path) => {
-                // TODO: Handle error case
-                if let Ok(found_test_files) = locate_test_files(path.as_str()) {
-                    for found_test_file in found_test_files {
-                        if let Some(path) = get_relative_path(&found_test_file, current_dir) {
-                            files.insert(path, TestIdContainer::full());
-                        } else {
-                            // TODO: Handle if path is not relative
-                        }
+                let found_test_files = locate_test_files(path.as_str(), search_markdown)
+                    .map_err(|error| (path.clone(), error))?;
+                for found_test_file in found_test_files {
+                    if let Some(path) = get_relative_path(&found_test_file, current_dir) {
+                        files.insert(path, TestIdContainer::full());
+                    } else {
+                        // TODO: Handle if path is not relative
                     }
                 }
             }
@@ -54,15 +58,24 @@ pub fn expand_test_paths(
         }
     }
 
-    files
+    Ok(files)
 }
 
-enum LocateFileError {
+pub enum LocateFileError {
     InvalidPattern(glob::PatternError),
     InvalidEntry(glob::GlobError),
 }
 
-fn locate_test_files(path: &str) -> Result<Vec<PathBuf>, LocateFileError> {
+impl fmt::Display for LocateFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocateFileError::InvalidPattern(error) => write!(f, "{}", error),
+            LocateFileError::InvalidEntry(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+fn locate_test_files(path: &str, search_markdown: bool) -> Result<Vec<PathBuf>, LocateFileError> {
     let mut output = vec![];
 
     let entries = glob::glob(path).map_err(LocateFileError::InvalidPattern)?;
@@ -71,10 +84,18 @@ fn locate_test_files(path: &str) -> Result<Vec<PathBuf>, LocateFileError> {
         if e.is_file() {
             output.push(e);
         } else if e.is_dir() {
-            // Look for `.au.toml` files in directory (recursively)
-            if let Some(search_path) = e.join("**/*.au.toml").to_str() {
-                let found_test_files = locate_test_files(search_path)?;
-                output.extend(found_test_files);
+            // Look for config files in directory (recursively)
+            let mut extensions = ConfigFormat::FILE_EXTENSIONS.to_vec();
+            if search_markdown {
+                extensions.push(ConfigFormat::MARKDOWN_FILE_EXTENSION);
+            }
+
+            for extension in extensions {
+                let pattern = format!("**/*.{}", extension);
+                if let Some(search_path) = e.join(pattern).to_str() {
+                    let found_test_files = locate_test_files(search_path, search_markdown)?;
+                    output.extend(found_test_files);
+                }
             }
         }
     }
@@ -82,6 +103,50 @@ fn locate_test_files(path: &str) -> Result<Vec<PathBuf>, LocateFileError> {
     Ok(output)
 }
 
+/// Locate the tests to convert. Directories are searched recursively for
+/// `trycmd` files and Idris-style test directories.
+pub fn locate_convertible_files(paths: &[PathBuf]) -> BTreeMap<PathBuf, SourceFormat> {
+    let mut files = BTreeMap::new();
+
+    for path in paths {
+        if let Some(format) = SourceFormat::from_path(path) {
+            files.insert(path.clone(), format);
+        } else if path.is_dir() {
+            for pattern in ["**/*.toml", "**/*.trycmd", "**/run"] {
+                let Some(entries) = path.join(pattern).to_str().map(glob::glob) else {
+                    continue;
+                };
+
+                for entry in entries.into_iter().flatten().flatten() {
+                    // Idris-style tests are identified by their `run` script
+                    let candidate = if entry.ends_with("run") {
+                        entry.parent().map(|x| x.to_path_buf()).unwrap_or_default()
+                    } else {
+                        entry
+                    };
+
+                    match SourceFormat::from_path(&candidate) {
+                        Some(SourceFormat::TrycmdCase) if !has_trycmd_files(&candidate) => {}
+                        Some(format) => {
+                            files.insert(candidate, format);
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+
+    files
+}
+
+// Avoid converting unrelated `.toml` files (e.g. `Cargo.toml`) found in directories
+fn has_trycmd_files(path: &Path) -> bool {
+    ["stdout", "stderr", "in", "out"]
+        .iter()
+        .any(|extension| path.with_extension(extension).exists())
+}
+
 fn get_relative_path(path: &Path, base: &Path) -> Option<RelativePathBuf> {
     if path.is_relative() {
         RelativePathBuf::from_path(path).ok()
@@ -1,11 +1,13 @@
 pub mod file;
 pub mod report;
+pub mod watch;
 
 use aureum::test_id::TestId;
 use aureum::utils::file as file_utils;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use file::TestPath;
-use std::path::Path;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 pub fn parse() -> Args {
@@ -14,8 +16,16 @@ pub fn parse() -> Args {
 
 /// Golden test runner for executables
 #[derive(Parser)]
-#[clap(bin_name = "aureum")]
+#[clap(
+    bin_name = "aureum",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    disable_help_subcommand = true
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Paths to config files
     #[arg(required = true)]
     pub paths: Vec<TestPath>,
@@ -32,15 +42,85 @@ pub struct Args {
     #[arg(long)]
     pub hide_absolute_paths: bool,
 
-    /// Run tests in parallel
+    /// Run tests in parallel, using one job per CPU
     #[arg(long)]
     pub run_tests_in_parallel: bool,
 
-    /// Print extra information about config files
+    /// Number of tests to run at the same time (Overrides `jobs` in `aureum.toml`) [default: 1]
+    #[arg(long)]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// Stop the test run after the first failing test
+    #[arg(long, conflicts_with = "max_failures")]
+    pub fail_fast: bool,
+
+    /// Stop the test run after this number of failing tests
+    #[arg(long)]
+    pub max_failures: Option<NonZeroUsize>,
+
+    /// Options: declaration, completion (Order in which results are reported when running tests in parallel)
+    #[arg(long, default_value = "declaration")]
+    pub stream_order: StreamOrder,
+
+    /// Run the tests in a random order (The seed is printed, so that the order can be reproduced)
+    #[arg(long)]
+    pub shuffle: bool,
+
+    /// Seed used to shuffle the tests (Implies `--shuffle`)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// List this number of the slowest tests after the test run (Also adds timing to TAP output)
+    #[arg(long)]
+    pub report_slowest: Option<NonZeroUsize>,
+
+    /// Number of times to rerun a failing test (Can be overridden by `retries` in config files)
+    #[arg(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// Only run the tests that failed in the last run (Stored in `.aureum/last-run.json`)
+    #[arg(long, conflicts_with = "failed_first")]
+    pub failed: bool,
+
+    /// Run the tests that failed in the last run before the other tests
+    #[arg(long, conflicts_with_all = ["shuffle", "seed"])]
+    pub failed_first: bool,
+
+    /// Do not store the results of the run (Also enabled by setting `AUREUM_NO_STATE`)
+    #[arg(long, conflicts_with_all = ["failed", "failed_first"])]
+    pub no_state: bool,
+
+    /// Number of times to run each test (A test fails if any of the runs fail)
+    #[arg(long, default_value = "1")]
+    pub repeat: NonZeroU32,
+
+    /// Also search directories for Markdown files (`.md`) with `console` blocks
+    #[arg(long)]
+    pub markdown: bool,
+
+    /// Update expected directories and files to match the output of failing tests
+    #[arg(long)]
+    pub accept: bool,
+
+    /// Keep running, and rerun the affected tests when config files, files they refer to or programs change
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Print extra information about config files and unfiltered output (Also adds timing to TAP output)
     #[arg(long)]
     pub verbose: bool,
 }
 
+#[derive(Subcommand)]
+pub enum Command {
+    /// Convert trycmd tests and Idris-style test directories to Aureum config files
+    Convert {
+        /// Paths to trycmd files (`.toml` and `.trycmd`), Idris-style test directories (containing `run` and `expected`) or directories to search
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
 impl FromStr for TestPath {
     type Err = &'static str;
 
@@ -79,3 +159,21 @@ impl FromStr for OutputFormat {
         }
     }
 }
+
+#[derive(Clone)]
+pub enum StreamOrder {
+    Declaration,
+    Completion,
+}
+
+impl FromStr for StreamOrder {
+    type Err = &'static str;
+
+    fn from_str(order: &str) -> Result<Self, Self::Err> {
+        match order {
+            "declaration" => Ok(Self::Declaration),
+            "completion" => Ok(Self::Completion),
+            _ => Err("Invalid stream order"),
+        }
+    }
+}
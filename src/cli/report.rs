@@ -1,13 +1,17 @@
+use crate::cli::file::LocateFileError;
+use aureum::convert::{Conversion, ConvertError, ConvertWarning};
 use aureum::formats::tree;
 use aureum::formats::tree::Tree::{self, Leaf, Node};
 use aureum::toml_config::{
-    ParsedTomlConfig, ProgramPath, Requirement, TestCaseValidationError, TomlConfigData,
-    TomlConfigError,
+    ConfigError, ParsedConfig, ProgramPath, Requirement, TestCaseValidationError, TomlConfigData,
 };
 use aureum::utils::file;
+use aureum::utils::source::SourceLocation;
 use colored::Colorize;
 use relative_path::RelativePathBuf;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
 
 pub fn print_no_config_files() {
     eprintln!(
@@ -16,8 +20,18 @@ pub fn print_no_config_files() {
     );
 }
 
-pub fn any_issues_in_toml_config(config: &ParsedTomlConfig) -> bool {
-    config.tests.values().any(|x| x.test_case.is_err())
+pub fn print_locate_file_error(path: &str, error: &LocateFileError) {
+    eprintln!(
+        "{} Failed to search for config files in '{}' ({})",
+        "error:".red().bold(),
+        path,
+        error
+    );
+}
+
+pub fn any_issues_in_toml_config(config: &ParsedConfig) -> bool {
+    !config.once_details.validation_errors.is_empty()
+        || config.tests.values().any(|x| x.test_case.is_err())
 }
 
 pub fn print_files_found(source_files: &[RelativePathBuf]) {
@@ -35,7 +49,7 @@ pub fn print_files_found(source_files: &[RelativePathBuf]) {
 
 pub fn print_config_details(
     source_file: RelativePathBuf,
-    config: &ParsedTomlConfig,
+    config: &ParsedConfig,
     verbose: bool,
     hide_absolute_paths: bool,
 ) {
@@ -47,26 +61,28 @@ pub fn print_config_details(
         if verbose {
             // Program to run
             {
-                let program_path = &test_details.program_path;
-                let program_to_run = match program_path {
-                    ProgramPath::NotSpecified => String::from("❌ Not specified"),
-                    ProgramPath::MissingProgram { requested_path: _ } => {
-                        String::from("❌ Not found")
-                    }
-                    ProgramPath::ResolvedPath {
-                        requested_path: _,
-                        resolved_path,
-                    } => {
-                        let path = if hide_absolute_paths {
-                            file::display_path(resolved_path)
-                        } else {
-                            resolved_path.display().to_string()
-                        };
-                        format!("✅ {}", path)
-                    }
-                };
-
-                let nodes = vec![str_to_tree(&program_to_run)];
+                let mut nodes = vec![];
+                for program_path in &test_details.program_paths {
+                    let program_to_run = match program_path {
+                        ProgramPath::NotSpecified => String::from("❌ Not specified"),
+                        ProgramPath::MissingProgram { requested_path: _ } => {
+                            String::from("❌ Not found")
+                        }
+                        ProgramPath::ResolvedPath {
+                            requested_path: _,
+                            resolved_path,
+                        } => {
+                            let path = if hide_absolute_paths {
+                                file::display_path(resolved_path)
+                            } else {
+                                resolved_path.display().to_string()
+                            };
+                            format!("✅ {}", path)
+                        }
+                    };
+
+                    nodes.push(str_to_tree(&program_to_run));
+                }
 
                 let heading = String::from("Program to run");
                 categories.push(Node(heading, nodes));
@@ -81,21 +97,19 @@ pub fn print_config_details(
         }
 
         // Validation errors
-        let heading = String::from("Validation errors");
         if let Err(validation_errors) = &test_details.test_case {
-            let nodes = validation_errors
-                .iter()
-                .map(|err| str_to_tree(&show_validation_error(err)))
-                .collect();
-
-            categories.push(Node(heading, nodes));
+            categories.push(validation_errors_to_tree(
+                &source_file,
+                validation_errors,
+                &test_details.error_locations,
+            ));
         }
 
         tests.push((test_id, categories))
     }
 
     let is_root = tests.len() == 1 && tests[0].0.is_root();
-    let nodes: Vec<Tree> = if is_root {
+    let mut nodes: Vec<Tree> = if is_root {
         tests.into_iter().next().unwrap().1
     } else {
         tests
@@ -104,21 +118,168 @@ pub fn print_config_details(
             .collect()
     };
 
+    // Errors in `[once]` are shared by all tests, so they are only shown once
+    let once_details = &config.once_details;
+    if !once_details.validation_errors.is_empty() {
+        let heading = String::from("[once]");
+        let errors = validation_errors_to_tree(
+            &source_file,
+            &once_details.validation_errors,
+            &once_details.error_locations,
+        );
+        nodes.insert(0, Node(heading, vec![errors]));
+    }
+
     let tree = Node(config_heading(source_file), nodes);
 
     print_tree(tree);
 }
 
-pub fn print_toml_config_error(source_file: RelativePathBuf, error: TomlConfigError) {
-    let msg = match error {
-        TomlConfigError::FailedToReadFile(_) => "Failed to read file",
-        TomlConfigError::FailedToParseTomlConfig(_) => "Failed to parse config file",
+fn validation_errors_to_tree(
+    source_file: &RelativePathBuf,
+    validation_errors: &BTreeSet<TestCaseValidationError>,
+    error_locations: &BTreeMap<TestCaseValidationError, SourceLocation>,
+) -> Tree {
+    let nodes = validation_errors
+        .iter()
+        .map(|err| {
+            let msg = show_validation_error(err);
+            match error_locations.get(err) {
+                Some(location) => str_to_tree(&format!(
+                    "{}\n{}",
+                    msg,
+                    location.show_snippet(source_file.as_str())
+                )),
+                None => str_to_tree(&msg),
+            }
+        })
+        .collect();
+
+    Node(String::from("Validation errors"), nodes)
+}
+
+pub fn print_toml_config_error(source_file: RelativePathBuf, error: ConfigError) {
+    let details = match error {
+        ConfigError::FailedToReadFile(err) => Node(
+            String::from("Failed to read file"),
+            vec![str_to_tree(&err.to_string())],
+        ),
+        ConfigError::FailedToParseConfig { message, location } => {
+            let details = match location {
+                Some(location) => format!(
+                    "{}\n{}",
+                    message,
+                    location.show_snippet(source_file.as_str())
+                ),
+                None => message,
+            };
+            Node(
+                String::from("Failed to parse config file"),
+                vec![str_to_tree(&details)],
+            )
+        }
     };
-    let tree = Node(config_heading(source_file), vec![str_to_tree(msg)]);
+    let tree = Node(config_heading(source_file), vec![details]);
+
+    print_tree(tree);
+}
+
+pub fn print_last_run_read_error(path: &Path, error: io::Error) {
+    eprintln!(
+        "{} Failed to read '{}' ({}). Remove the file to forget the results of previous runs.",
+        "error:".red().bold(),
+        path.display(),
+        error
+    );
+}
+
+pub fn print_no_state_conflict(env_var_name: &str) {
+    eprintln!(
+        "{} '--failed' and '--failed-first' cannot be used when '{}' is set",
+        "error:".red().bold(),
+        env_var_name
+    );
+}
+
+pub fn print_no_last_run(path: &Path) {
+    eprintln!(
+        "{} No results of a previous run found in '{}'",
+        "error:".red().bold(),
+        path.display()
+    );
+}
+
+pub fn print_last_run_error(path: &Path, error: io::Error) {
+    eprintln!(
+        "{} Failed to write '{}' ({})",
+        "warning:".yellow().bold(),
+        path.display(),
+        error
+    );
+}
+
+pub fn print_watching() {
+    eprintln!();
+    eprintln!("👀 Watching for changes (Press Ctrl-C to stop)");
+}
+
+pub fn print_accepted_tests(accepted_tests: &[(String, io::Result<()>)]) {
+    let heading = format!("📝 Accepted changes for {} tests", accepted_tests.len());
+    let tree = Node(
+        heading,
+        accepted_tests
+            .iter()
+            .map(|(test_id, result)| match result {
+                Ok(()) => str_to_tree(&format!("✅ {}", test_id)),
+                Err(err) => str_to_tree(&format!("❌ {} ({})", test_id, err)),
+            })
+            .collect(),
+    );
 
     print_tree(tree);
 }
 
+pub fn print_no_convertible_files() {
+    eprintln!(
+        "{} No trycmd files or Idris-style tests found for the given paths",
+        "error:".red().bold(),
+    );
+}
+
+pub fn print_conversions(conversions: &[(PathBuf, Result<Conversion, ConvertError>)]) {
+    let converted = conversions.iter().filter(|(_, x)| x.is_ok()).count();
+    let heading = format!("🔁 Converted {} of {} tests", converted, conversions.len());
+
+    let nodes = conversions
+        .iter()
+        .map(|(source, result)| match result {
+            Ok(conversion) if conversion.warnings.is_empty() => str_to_tree(&format!(
+                "✅ {} → {}",
+                source.display(),
+                conversion.output_file.display()
+            )),
+            Ok(conversion) => Node(
+                format!(
+                    "⚠️  {} → {}",
+                    source.display(),
+                    conversion.output_file.display()
+                ),
+                conversion
+                    .warnings
+                    .iter()
+                    .map(|x| str_to_tree(&show_convert_warning(x)))
+                    .collect(),
+            ),
+            Err(err) => Node(
+                format!("❌ {}", source.display()),
+                vec![str_to_tree(&show_convert_error(err))],
+            ),
+        })
+        .collect();
+
+    print_tree(Node(heading, nodes));
+}
+
 fn print_tree(tree: Tree) {
     let content = tree::draw_tree(&tree).unwrap_or_else(|_| String::from("Failed to draw tree\n"));
 
@@ -188,15 +349,137 @@ fn show_validation_error(validation_error: &TestCaseValidationError) -> String {
         TestCaseValidationError::ExpectationRequired => {
             String::from("At least one expectation is required")
         }
+        TestCaseValidationError::InvalidExpectedFilePath(path) => {
+            format!(
+                "The expected file '{}' must be a relative path inside the sandbox",
+                path
+            )
+        }
+        TestCaseValidationError::ExpectedFileContentRequired(path) => {
+            format!(
+                "The expected file '{}' requires either content or `exists = false`",
+                path
+            )
+        }
+        TestCaseValidationError::HookCommandRequired => {
+            String::from("Setup, teardown and filter commands must not be empty")
+        }
+        TestCaseValidationError::StepRequired => String::from("At least one step is required"),
+        TestCaseValidationError::InvalidDuration(duration) => {
+            format!(
+                "Invalid duration '{}' (expected a number followed by 'ms', 's' or 'm')",
+                duration
+            )
+        }
+        TestCaseValidationError::InteractionRequiresPty => {
+            String::from("The field 'interaction' requires 'pty = true'")
+        }
+        TestCaseValidationError::InvalidCompareMode(mode) => {
+            format!(
+                "Invalid compare mode '{}' (expected 'exact', 'json', 'unordered-lines' or {{ numeric_tolerance = ... }})",
+                mode
+            )
+        }
+        TestCaseValidationError::InvalidJsonPointer(path) => {
+            format!(
+                "Invalid JSON pointer '{}' (expected e.g. '/timestamp')",
+                path
+            )
+        }
+        TestCaseValidationError::InvalidNumericTolerance(tolerance) => {
+            format!(
+                "Invalid numeric tolerance '{}' (expected a non-negative number)",
+                tolerance
+            )
+        }
+        TestCaseValidationError::UnknownField { field, suggestion } => match suggestion {
+            Some(suggestion) => {
+                format!("Unknown field '{}' (Did you mean '{}'?)", field, suggestion)
+            }
+            None => format!("Unknown field '{}'", field),
+        },
     };
 
     format!("❌ {}", msg)
 }
 
+fn show_convert_warning(warning: &ConvertWarning) -> String {
+    match warning {
+        ConvertWarning::UnsupportedField(field) => {
+            format!("The field '{}' is not supported and was skipped", field)
+        }
+        ConvertWarning::UnsupportedStatus(status) => {
+            format!(
+                "The status '{}' is not supported (The exit code is not checked)",
+                status
+            )
+        }
+        ConvertWarning::MissingProgram => {
+            String::from("No program is specified (Set `program` manually)")
+        }
+        ConvertWarning::CargoBinary(name) => {
+            format!(
+                "The Cargo binary '{}' is looked up using `PATH` (Set `program` to its path if needed)",
+                name
+            )
+        }
+        ConvertWarning::CombinedOutput => String::from(
+            "Standard error is no longer combined with standard output, and is not checked",
+        ),
+        ConvertWarning::WildcardsInFile(path) => {
+            format!(
+                "The file '{}' contains wildcards, which must be replaced by the exact output",
+                path
+            )
+        }
+        ConvertWarning::WildcardsInCommand(command) => {
+            format!(
+                "The output of `{}` contains wildcards, which must be replaced by the exact output",
+                command
+            )
+        }
+        ConvertWarning::InputDir(path) => {
+            format!(
+                "The input directory '{}' is not copied to the sandbox",
+                path
+            )
+        }
+        ConvertWarning::EnvAssignment(assignment) => {
+            format!(
+                "The environment variable assignment '{}' is not supported and was skipped",
+                assignment
+            )
+        }
+        ConvertWarning::RunsInSandbox => String::from(
+            "The commands are converted to steps, which run in an empty sandbox directory",
+        ),
+        ConvertWarning::ScriptArgument => {
+            String::from("The `run` script uses `$1`, which is not provided by Aureum")
+        }
+    }
+}
+
+fn show_convert_error(error: &ConvertError) -> String {
+    match error {
+        ConvertError::FailedToReadFile(err) => format!("Failed to read file ({})", err),
+        ConvertError::FailedToParse(message) => format!("Failed to parse file\n{}", message),
+        ConvertError::UnterminatedQuote(command) => {
+            format!("Unterminated quote in command `{}`", command)
+        }
+        ConvertError::NoCommands => {
+            String::from("No commands found (Expected lines starting with `$ `)")
+        }
+        ConvertError::OutputFileExists(path) => {
+            format!("The file '{}' already exists", path.display())
+        }
+        ConvertError::FailedToWriteFile(err) => format!("Failed to write file ({})", err),
+    }
+}
+
 fn show_presence(value: bool) -> String {
     String::from(if value { "✅" } else { "❌" })
 }
 
 fn str_to_tree(msg: &str) -> Tree {
-    Leaf(vec![msg.to_owned()])
+    Leaf(msg.lines().map(|x| x.to_owned()).collect())
 }
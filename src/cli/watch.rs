@@ -0,0 +1,51 @@
+//! Detect changed files by polling their modification times, which works the
+//! same way on all platforms
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The modification time of each watched file. `None` means that the file does
+/// not exist (or that its modification time is not available).
+pub struct Snapshot(BTreeMap<PathBuf, Option<SystemTime>>);
+
+impl Snapshot {
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Snapshot {
+        Snapshot(
+            paths
+                .into_iter()
+                .map(|path| {
+                    let modified = modified_time(&path);
+                    (path, modified)
+                })
+                .collect(),
+        )
+    }
+
+    /// The watched files that were created, modified or removed since the snapshot was taken
+    pub fn changed_paths(&self) -> BTreeSet<PathBuf> {
+        self.0
+            .iter()
+            .filter(|(path, modified)| modified_time(path) != **modified)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+pub fn wait() {
+    thread::sleep(POLL_INTERVAL)
+}
+
+pub fn clear_screen() {
+    print!("\x1B[2J\x1B[H");
+    let _ = io::stdout().flush();
+}
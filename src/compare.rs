@@ -0,0 +1,516 @@
+//! Compare the output of a program against the expected output
+
+use serde_json::{Number, Value};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Configured using `compare`, which `stdout_compare` and `stderr_compare`
+/// override for a single stream
+#[derive(Clone, PartialEq, Debug, Default)]
+pub enum CompareMode {
+    #[default]
+    Exact,
+    Json {
+        ignored_paths: Vec<String>, // JSON pointers, e.g. `/timestamp`
+    },
+    UnorderedLines,
+    /// Numbers match if they are within either tolerance, e.g. `relative = 0.01`
+    /// allows a difference of 1% of the larger number
+    NumericTolerance {
+        absolute: f64,
+        relative: f64,
+    },
+}
+
+/// How the differences between the expected and actual output are shown
+#[derive(PartialEq, Debug)]
+pub enum OutputDiff {
+    Lines, // A line diff of the complete output
+    UnmatchedLines {
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    Differences(Vec<String>), // E.g. ``"`/items/3/name`: expected "a", got "b""``
+    /// Like `Differences`, with the byte ranges of the numbers outside the
+    /// tolerance, so that they can be highlighted in the output
+    NumericDifferences {
+        differences: Vec<String>, // E.g. `"Line 2: expected 1.5, got 1.6 (outside tolerance)"`
+        expected_numbers: Vec<Range<usize>>,
+        got_numbers: Vec<Range<usize>>,
+    },
+}
+
+impl CompareMode {
+    pub fn is_match(&self, expected: &str, got: &str) -> bool {
+        match self.diff(expected, got) {
+            OutputDiff::Lines => expected == got,
+            OutputDiff::UnmatchedLines {
+                missing,
+                unexpected,
+            } => missing.is_empty() && unexpected.is_empty(),
+            OutputDiff::Differences(differences)
+            | OutputDiff::NumericDifferences { differences, .. } => differences.is_empty(),
+        }
+    }
+
+    pub fn diff(&self, expected: &str, got: &str) -> OutputDiff {
+        match self {
+            Self::Exact => OutputDiff::Lines,
+            Self::Json { ignored_paths } => {
+                OutputDiff::Differences(json_differences(expected, got, ignored_paths))
+            }
+            Self::UnorderedLines => unmatched_lines(expected, got),
+            Self::NumericTolerance { absolute, relative } => {
+                numeric_differences(expected, got, *absolute, *relative)
+            }
+        }
+    }
+}
+
+// UNORDERED LINES
+
+// The lines are compared as multisets, so each line must occur the same number of times
+fn unmatched_lines(expected: &str, got: &str) -> OutputDiff {
+    OutputDiff::UnmatchedLines {
+        missing: subtract_lines(expected, got),
+        unexpected: subtract_lines(got, expected),
+    }
+}
+
+// Returns the lines in `x` that are not matched by a line in `y`, in the original order
+fn subtract_lines(x: &str, y: &str) -> Vec<String> {
+    let mut counts = BTreeMap::<&str, usize>::new();
+    for line in y.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+
+    let mut lines = vec![];
+    for line in x.lines() {
+        match counts.get_mut(line) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => lines.push(line.to_owned()),
+        }
+    }
+
+    lines
+}
+
+// NUMERIC TOLERANCE
+
+#[derive(PartialEq, Debug)]
+enum Token<'a> {
+    Text(&'a str),
+    Number(&'a str),
+}
+
+// Lines with the same text are compared token by token, while other lines are reported in full
+fn numeric_differences(expected: &str, got: &str, absolute: f64, relative: f64) -> OutputDiff {
+    let expected_lines = expected.lines().collect::<Vec<_>>();
+    let got_lines = got.lines().collect::<Vec<_>>();
+
+    let mut differences = vec![];
+    let mut expected_numbers = vec![];
+    let mut got_numbers = vec![];
+    for i in 0..expected_lines.len().max(got_lines.len()) {
+        let line_number = i + 1;
+        match (expected_lines.get(i), got_lines.get(i)) {
+            (Some(expected_line), Some(got_line)) => {
+                let expected_tokens = tokenize(expected_line);
+                let got_tokens = tokenize(got_line);
+
+                if !is_same_text(&expected_tokens, &got_tokens) {
+                    differences.push(format!(
+                        "Line {}: expected {:?}, got {:?}",
+                        line_number, expected_line, got_line
+                    ));
+                    continue;
+                }
+
+                for (x, y) in expected_tokens.iter().zip(&got_tokens) {
+                    if let (Token::Number(x), Token::Number(y)) = (x, y) {
+                        if !is_within_tolerance(x, y, absolute, relative) {
+                            differences.push(format!(
+                                "Line {}: expected {}, got {} (outside tolerance)",
+                                line_number, x, y
+                            ));
+                            expected_numbers.push(range_within(expected, x));
+                            got_numbers.push(range_within(got, y));
+                        }
+                    }
+                }
+            }
+            (Some(expected_line), None) => differences.push(format!(
+                "Line {}: expected {:?}, got nothing",
+                line_number, expected_line
+            )),
+            (None, Some(got_line)) => differences.push(format!(
+                "Line {}: expected nothing, got {:?}",
+                line_number, got_line
+            )),
+            (None, None) => {}
+        }
+    }
+
+    if differences.is_empty() && expected.ends_with('\n') != got.ends_with('\n') {
+        differences.push(if expected.ends_with('\n') {
+            String::from("Missing newline at end")
+        } else {
+            String::from("Unexpected newline at end")
+        });
+    }
+
+    OutputDiff::NumericDifferences {
+        differences,
+        expected_numbers,
+        got_numbers,
+    }
+}
+
+// The byte range of `part` within `text`, which `part` must be a slice of
+fn range_within(text: &str, part: &str) -> Range<usize> {
+    let start = part.as_ptr() as usize - text.as_ptr() as usize;
+    start..start + part.len()
+}
+
+fn is_same_text(expected: &[Token], got: &[Token]) -> bool {
+    expected.len() == got.len()
+        && expected.iter().zip(got).all(|pair| match pair {
+            (Token::Text(x), Token::Text(y)) => x == y,
+            (Token::Number(_), Token::Number(_)) => true,
+            _ => false,
+        })
+}
+
+fn is_within_tolerance(expected: &str, got: &str, absolute: f64, relative: f64) -> bool {
+    match (expected.parse::<f64>(), got.parse::<f64>()) {
+        (Ok(x), Ok(y)) => {
+            let difference = (x - y).abs();
+            x == y || difference <= absolute || difference <= relative * x.abs().max(y.abs())
+        }
+        _ => expected == got,
+    }
+}
+
+// Splits a line into numbers (e.g. `-1.5e-3`) and the text between them
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut text_start = 0;
+    let mut i = 0;
+
+    while i < line.len() {
+        match number_length(&line[i..]) {
+            // Dotted runs of digits (e.g. the version `1.2.3`) are text
+            Some(length) if continues_with_fraction(&line[i + length..]) => {
+                i += length;
+                i += line[i..]
+                    .bytes()
+                    .take_while(|x| x.is_ascii_digit() || *x == b'.')
+                    .count();
+            }
+            Some(length) => {
+                if text_start < i {
+                    tokens.push(Token::Text(&line[text_start..i]));
+                }
+                tokens.push(Token::Number(&line[i..i + length]));
+                i += length;
+                text_start = i;
+            }
+            None => i += line[i..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+
+    if text_start < line.len() {
+        tokens.push(Token::Text(&line[text_start..]));
+    }
+
+    tokens
+}
+
+fn continues_with_fraction(text: &str) -> bool {
+    let mut bytes = text.bytes();
+    bytes.next() == Some(b'.') && bytes.next().is_some_and(|x| x.is_ascii_digit())
+}
+
+// Returns the length of the number at the start of `text`, if any
+fn number_length(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let count_digits = |start: usize| {
+        bytes[start.min(bytes.len())..]
+            .iter()
+            .take_while(|x| x.is_ascii_digit())
+            .count()
+    };
+
+    let mut i = usize::from(matches!(bytes.first(), Some(b'-' | b'+')));
+
+    let integer_digits = count_digits(i);
+    i += integer_digits;
+
+    let mut fraction_digits = 0;
+    if bytes.get(i) == Some(&b'.') {
+        fraction_digits = count_digits(i + 1);
+        if fraction_digits > 0 {
+            i += 1 + fraction_digits;
+        }
+    }
+
+    if integer_digits == 0 && fraction_digits == 0 {
+        return None;
+    }
+
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(i + 1), Some(b'-' | b'+')));
+        let exponent_digits = count_digits(i + 1 + sign);
+        if exponent_digits > 0 {
+            i += 1 + sign + exponent_digits;
+        }
+    }
+
+    Some(i)
+}
+
+// JSON
+
+fn json_differences(expected: &str, got: &str, ignored_paths: &[String]) -> Vec<String> {
+    let (expected, got) = match (parse_json(expected), parse_json(got)) {
+        (Ok(expected), Ok(got)) => (expected, got),
+        (Err(err), _) => return vec![format!("Failed to parse expected output as JSON ({})", err)],
+        (_, Err(err)) => return vec![format!("Failed to parse output as JSON ({})", err)],
+    };
+
+    let mut differences = vec![];
+    compare_json_values(
+        &mut differences,
+        String::new(),
+        expected.as_ref(),
+        got.as_ref(),
+        ignored_paths,
+    );
+    differences
+}
+
+// Empty output is allowed, e.g. for `expected_stderr = ""`
+fn parse_json(text: &str) -> serde_json::Result<Option<Value>> {
+    if text.trim().is_empty() {
+        Ok(None)
+    } else {
+        serde_json::from_str(text).map(Some)
+    }
+}
+
+// `None` means that the value is missing
+fn compare_json_values(
+    differences: &mut Vec<String>,
+    path: String,
+    expected: Option<&Value>,
+    got: Option<&Value>,
+    ignored_paths: &[String],
+) {
+    if ignored_paths.contains(&path) {
+        return;
+    }
+
+    match (expected, got) {
+        (Some(Value::Object(expected)), Some(Value::Object(got))) => {
+            let mut keys = expected.keys().chain(got.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                compare_json_values(
+                    differences,
+                    format!("{}/{}", path, escape_json_pointer(key)),
+                    expected.get(key),
+                    got.get(key),
+                    ignored_paths,
+                );
+            }
+        }
+        (Some(Value::Array(expected)), Some(Value::Array(got))) => {
+            for i in 0..expected.len().max(got.len()) {
+                compare_json_values(
+                    differences,
+                    format!("{}/{}", path, i),
+                    expected.get(i),
+                    got.get(i),
+                    ignored_paths,
+                );
+            }
+        }
+        (Some(Value::Number(x)), Some(Value::Number(y))) if is_same_number(x, y) => {}
+        (expected, got) if expected == got => {}
+        (expected, got) => differences.push(format!(
+            "{}: expected {}, got {}",
+            show_json_path(&path),
+            show_json_value(expected),
+            show_json_value(got)
+        )),
+    }
+}
+
+// Integers are compared exactly, since large integers cannot be represented
+// as floats. An integer and a float (e.g. `1` and `1.0`) are compared as floats.
+fn is_same_number(x: &Number, y: &Number) -> bool {
+    if x.is_f64() || y.is_f64() {
+        x.as_f64() == y.as_f64()
+    } else {
+        x.as_i64() == y.as_i64() && x.as_u64() == y.as_u64()
+    }
+}
+
+fn escape_json_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn show_json_path(path: &str) -> String {
+    if path.is_empty() {
+        String::from("(root)")
+    } else {
+        format!("`{}`", path)
+    }
+}
+
+fn show_json_value(value: Option<&Value>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => String::from("nothing"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn absolute(tolerance: f64) -> CompareMode {
+        CompareMode::NumericTolerance {
+            absolute: tolerance,
+            relative: 0.0,
+        }
+    }
+
+    fn json(ignored_paths: &[&str]) -> CompareMode {
+        CompareMode::Json {
+            ignored_paths: ignored_paths.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_json_ignores_formatting_and_key_order() {
+        let expected = "{\"a\": 1, \"b\": [true, null]}\n";
+        let got = "{\"b\":[true,null],\"a\":1.0}";
+
+        assert!(json(&[]).is_match(expected, got));
+        assert!(json(&[]).is_match("", "\n"));
+    }
+
+    #[test]
+    fn test_json_differences() {
+        let expected = r#"{"items": [{"name": "a"}, {"name": "b"}], "a/b": 1}"#;
+        let got = r#"{"items": [{"name": "a"}, {"name": "c"}, 3], "timestamp": 123}"#;
+
+        assert_eq!(
+            json(&["/timestamp"]).diff(expected, got),
+            OutputDiff::Differences(vec![
+                String::from("`/a~1b`: expected 1, got nothing"),
+                String::from("`/items/1/name`: expected \"b\", got \"c\""),
+                String::from("`/items/2`: expected nothing, got 3"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_json_large_integers() {
+        assert!(json(&[]).is_match("18446744073709551615", "18446744073709551615"));
+        assert!(!json(&[]).is_match("9007199254740993", "9007199254740992"));
+        assert!(!json(&[]).is_match("-9007199254740993", "-9007199254740992"));
+    }
+
+    #[test]
+    fn test_json_invalid_output() {
+        let OutputDiff::Differences(differences) = json(&[]).diff("{}", "Error\n") else {
+            panic!("Expected differences");
+        };
+
+        assert_eq!(differences.len(), 1);
+        assert!(differences[0].starts_with("Failed to parse output as JSON"));
+    }
+
+    #[test]
+    fn test_unordered_lines() {
+        let expected = "a\nb\nb\nc\n";
+
+        assert!(CompareMode::UnorderedLines.is_match(expected, "c\nb\na\nb\n"));
+        assert_eq!(
+            CompareMode::UnorderedLines.diff(expected, "d\nb\na\n"),
+            OutputDiff::UnmatchedLines {
+                missing: vec![String::from("b"), String::from("c")],
+                unexpected: vec![String::from("d")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_numeric_tolerance() {
+        let expected = "x = 1.000000, y = -2.5e-3\nDone in 3 steps\n";
+        let got = "x = 1.0000004, y = -2.5000001e-3\nDone in 3 steps\n";
+
+        assert!(absolute(1e-6).is_match(expected, got));
+        assert!(!absolute(1.0).is_match("aureum 1.2.3\n", "aureum 1.2.4\n"));
+        assert!(!absolute(1e-9).is_match(expected, got));
+    }
+
+    #[test]
+    fn test_numeric_tolerance_relative() {
+        let tolerance = CompareMode::NumericTolerance {
+            absolute: 0.0,
+            relative: 0.01,
+        };
+
+        assert!(tolerance.is_match("1000\n", "1009\n"));
+        assert!(!tolerance.is_match("1000\n", "1011\n"));
+        assert!(!tolerance.is_match("0.001\n", "0.002\n"));
+    }
+
+    #[test]
+    fn test_numeric_tolerance_differences() {
+        let expected = "a 1.5 b 2\nc\n";
+        let got = "a 1.6 b 2.1\nd\ne\n";
+
+        assert_eq!(
+            absolute(1e-6).diff(expected, got),
+            OutputDiff::NumericDifferences {
+                differences: vec![
+                    String::from("Line 1: expected 1.5, got 1.6 (outside tolerance)"),
+                    String::from("Line 1: expected 2, got 2.1 (outside tolerance)"),
+                    String::from("Line 2: expected \"c\", got \"d\""),
+                    String::from("Line 3: expected nothing, got \"e\""),
+                ],
+                expected_numbers: vec![2..5, 8..9],
+                got_numbers: vec![2..5, 8..11],
+            }
+        );
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("v1.2.3: -4e5x .5, 2."),
+            vec![
+                Token::Text("v1.2.3: "),
+                Token::Number("-4e5"),
+                Token::Text("x "),
+                Token::Number(".5"),
+                Token::Text(", "),
+                Token::Number("2"),
+                Token::Text("."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exact() {
+        assert!(CompareMode::Exact.is_match("a\n", "a\n"));
+        assert!(!CompareMode::Exact.is_match("a\n", "a"));
+        assert_eq!(CompareMode::Exact.diff("a", "b"), OutputDiff::Lines);
+    }
+}
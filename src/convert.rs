@@ -0,0 +1,570 @@
+//! Convert tests written for other golden test runners to Aureum config files
+//!
+//! The following formats are supported:
+//! - `trycmd` test cases: A `.toml` file together with the external files `.stdin`,
+//!   `.stdout` and `.stderr`, and the directories `.in` and `.out`
+//! - `trycmd` scripts: A `.trycmd` file containing commands (prefixed by `$ `) and their output
+//! - Idris-style tests: A directory containing a `run` script and an `expected` file
+
+use crate::toml_config::ConfigFormat;
+use crate::utils::string;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum SourceFormat {
+    TrycmdCase,
+    TrycmdScript,
+    IdrisTest,
+}
+
+impl SourceFormat {
+    pub fn from_path(path: &Path) -> Option<SourceFormat> {
+        if path.is_dir() {
+            let is_idris_test = path.join("run").is_file() && path.join("expected").is_file();
+            return is_idris_test.then_some(SourceFormat::IdrisTest);
+        }
+
+        let file_name = path.file_name()?.to_str()?;
+        let is_aureum_config = ConfigFormat::FILE_EXTENSIONS
+            .iter()
+            .any(|extension| file_name.ends_with(&format!(".{}", extension)));
+
+        if file_name.ends_with(".trycmd") {
+            Some(SourceFormat::TrycmdScript)
+        } else if file_name.ends_with(".toml") && !is_aureum_config {
+            Some(SourceFormat::TrycmdCase)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Conversion {
+    pub output_file: PathBuf,
+    pub content: String,
+    pub warnings: Vec<ConvertWarning>,
+}
+
+/// Functionality that could not be translated to Aureum
+#[derive(PartialEq, Eq, Debug)]
+pub enum ConvertWarning {
+    UnsupportedField(String),
+    UnsupportedStatus(String),
+    MissingProgram,
+    CargoBinary(String),
+    CombinedOutput,
+    WildcardsInFile(String),
+    WildcardsInCommand(String),
+    InputDir(String),
+    EnvAssignment(String),
+    RunsInSandbox,
+    ScriptArgument,
+}
+
+pub enum ConvertError {
+    FailedToReadFile(io::Error),
+    FailedToParse(String),
+    UnterminatedQuote(String),
+    NoCommands,
+    OutputFileExists(PathBuf),
+    FailedToWriteFile(io::Error),
+}
+
+pub fn convert(path: &Path, format: SourceFormat) -> Result<Conversion, ConvertError> {
+    match format {
+        SourceFormat::TrycmdCase => convert_trycmd_case(path),
+        SourceFormat::TrycmdScript => convert_trycmd_script(path),
+        SourceFormat::IdrisTest => convert_idris_test(path),
+    }
+}
+
+/// Write the converted config file. Existing files are never replaced.
+pub fn write_conversion(conversion: &Conversion) -> Result<(), ConvertError> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&conversion.output_file)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => {
+                ConvertError::OutputFileExists(conversion.output_file.clone())
+            }
+            _ => ConvertError::FailedToWriteFile(err),
+        })?;
+
+    file.write_all(conversion.content.as_bytes())
+        .map_err(ConvertError::FailedToWriteFile)
+}
+
+// TRYCMD TEST CASES
+
+#[derive(Deserialize)]
+struct TrycmdCase {
+    bin: Option<TrycmdBin>,
+    args: Option<TrycmdArgs>,
+    status: Option<TrycmdStatus>,
+    #[serde(default)]
+    stderr_to_stdout: bool,
+    fs: Option<TrycmdFs>,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+struct TrycmdBin {
+    name: Option<String>,
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TrycmdArgs {
+    Line(String),
+    List(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TrycmdStatus {
+    Code { code: i32 },
+    Name(String),
+}
+
+#[derive(Deserialize)]
+struct TrycmdFs {
+    sandbox: Option<bool>,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, IgnoredAny>,
+}
+
+fn convert_trycmd_case(path: &Path) -> Result<Conversion, ConvertError> {
+    let content = fs::read_to_string(path).map_err(ConvertError::FailedToReadFile)?;
+    let case: TrycmdCase =
+        toml::from_str(&content).map_err(|err| ConvertError::FailedToParse(err.to_string()))?;
+
+    let mut warnings = vec![];
+    let mut fields = Fields::new();
+
+    for field in case.unknown_fields.keys() {
+        warnings.push(ConvertWarning::UnsupportedField(field.clone()));
+    }
+
+    match case.bin {
+        Some(TrycmdBin {
+            path: Some(path), ..
+        }) => fields.push("program", toml_string(&path)),
+        Some(TrycmdBin {
+            name: Some(name), ..
+        }) => {
+            warnings.push(ConvertWarning::CargoBinary(name.clone()));
+            fields.push("program", toml_string(&name));
+        }
+        _ => warnings.push(ConvertWarning::MissingProgram),
+    }
+
+    let arguments = match case.args {
+        Some(TrycmdArgs::Line(line)) => string::split_command(&line)
+            .ok_or_else(|| ConvertError::UnterminatedQuote(line.clone()))?,
+        Some(TrycmdArgs::List(arguments)) => arguments,
+        None => vec![],
+    };
+    if !arguments.is_empty() {
+        fields.push("program_arguments", toml_string_array(&arguments));
+    }
+
+    // External files are named after the test case, e.g. `help.stdout` for `help.toml`
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let name = file_stem(path, ".toml");
+    let sibling = |extension: &str| format!("{}.{}", name, extension);
+
+    if dir.join(sibling("stdin")).is_file() {
+        fields.push("stdin", toml_file_reference(&sibling("stdin")));
+    }
+
+    fields.push_separator();
+
+    for stream in ["stdout", "stderr"] {
+        let file_name = sibling(stream);
+        let Ok(expected_output) = fs::read_to_string(dir.join(&file_name)) else {
+            continue;
+        };
+
+        if contains_wildcards(&expected_output) {
+            warnings.push(ConvertWarning::WildcardsInFile(file_name.clone()));
+        }
+        fields.push(
+            &format!("expected_{}", stream),
+            toml_file_reference(&file_name),
+        );
+    }
+
+    if case.stderr_to_stdout {
+        warnings.push(ConvertWarning::CombinedOutput);
+    }
+
+    // Commands are expected to succeed, unless specified otherwise
+    let expected_exit_code = match case.status {
+        Some(TrycmdStatus::Code { code }) => Some(code),
+        Some(TrycmdStatus::Name(status)) => convert_status(&mut warnings, &status),
+        None => Some(0),
+    };
+    if let Some(expected_exit_code) = expected_exit_code {
+        fields.push("expected_exit_code", expected_exit_code.to_string());
+    }
+
+    if let Some(fs) = case.fs {
+        for field in fs.unknown_fields.keys() {
+            warnings.push(ConvertWarning::UnsupportedField(format!("fs.{}", field)));
+        }
+        if fs.sandbox == Some(true) && !dir.join(sibling("out")).is_dir() {
+            warnings.push(ConvertWarning::UnsupportedField(String::from("fs.sandbox")));
+        }
+    }
+
+    if dir.join(sibling("in")).is_dir() {
+        warnings.push(ConvertWarning::InputDir(sibling("in")));
+    }
+
+    // The output directory is compared against the complete sandbox
+    if dir.join(sibling("out")).is_dir() {
+        fields.push("expected_dir", toml_string(&sibling("out")));
+    }
+
+    Ok(Conversion {
+        output_file: dir.join(format!("{}.au.toml", name)),
+        content: fields.to_toml(),
+        warnings,
+    })
+}
+
+// TRYCMD SCRIPTS
+
+struct ScriptCommand {
+    line: String, // Without the `$ ` prefix
+    status: Option<String>,
+    expected_output: String,
+}
+
+fn convert_trycmd_script(path: &Path) -> Result<Conversion, ConvertError> {
+    let content = fs::read_to_string(path).map_err(ConvertError::FailedToReadFile)?;
+    let commands = parse_trycmd_script(&content);
+
+    if commands.is_empty() {
+        return Err(ConvertError::NoCommands);
+    }
+
+    let mut warnings = vec![ConvertWarning::CombinedOutput];
+    if commands.len() > 1 {
+        warnings.push(ConvertWarning::RunsInSandbox);
+    }
+
+    let mut steps = vec![];
+    for command in commands {
+        let words = string::split_command(&command.line)
+            .ok_or_else(|| ConvertError::UnterminatedQuote(command.line.clone()))?;
+
+        // Environment variables can be set in front of the program, e.g. `$ FOO=1 my-program`
+        let mut words = words.into_iter().peekable();
+        while let Some(assignment) = words.next_if(|x| is_env_assignment(x)) {
+            warnings.push(ConvertWarning::EnvAssignment(assignment));
+        }
+
+        let mut fields = Fields::new();
+        fields.push("program", toml_string(&words.next().unwrap_or_default()));
+        let arguments = words.collect::<Vec<_>>();
+        if !arguments.is_empty() {
+            fields.push("program_arguments", toml_string_array(&arguments));
+        }
+
+        if contains_wildcards(&command.expected_output) {
+            warnings.push(ConvertWarning::WildcardsInCommand(command.line.clone()));
+        }
+        fields.push("expected_stdout", toml_string(&command.expected_output));
+
+        let expected_exit_code = match &command.status {
+            Some(status) => convert_status(&mut warnings, status),
+            None => Some(0),
+        };
+        if let Some(expected_exit_code) = expected_exit_code {
+            fields.push("expected_exit_code", expected_exit_code.to_string());
+        }
+
+        steps.push(fields);
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let name = file_stem(path, ".trycmd");
+
+    Ok(Conversion {
+        output_file: dir.join(format!("{}.au.toml", name)),
+        content: steps_to_toml(steps),
+        warnings,
+    })
+}
+
+// Lines starting with `> ` continue the previous command, and a line starting
+// with `? ` specifies the expected status of the command
+fn parse_trycmd_script(content: &str) -> Vec<ScriptCommand> {
+    let mut commands: Vec<ScriptCommand> = vec![];
+    let mut is_reading_command = false;
+
+    for line in content.lines() {
+        if let Some(command_line) = line.strip_prefix("$ ") {
+            commands.push(ScriptCommand {
+                line: command_line.to_owned(),
+                status: None,
+                expected_output: String::new(),
+            });
+            is_reading_command = true;
+            continue;
+        }
+
+        let Some(command) = commands.last_mut() else {
+            continue;
+        };
+
+        match line.strip_prefix("> ") {
+            Some(continuation) if is_reading_command => {
+                let line = command.line.trim_end().trim_end_matches('\\').trim_end();
+                command.line = format!("{} {}", line, continuation);
+            }
+            _ => {
+                is_reading_command = false;
+                if let Some(status) = line.strip_prefix("? ") {
+                    command.status = Some(status.trim().to_owned());
+                } else {
+                    command.expected_output.push_str(line);
+                    command.expected_output.push('\n');
+                }
+            }
+        }
+    }
+
+    // Empty lines between commands are only used as separators
+    for command in &mut commands {
+        let trimmed_length = command.expected_output.trim_end_matches('\n').len();
+        command.expected_output.truncate(trimmed_length);
+        if trimmed_length > 0 {
+            command.expected_output.push('\n');
+        }
+    }
+
+    commands
+}
+
+fn is_env_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty() && name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+        }
+        None => false,
+    }
+}
+
+// IDRIS-STYLE TESTS
+
+// The `run` script is run using `sh`, and its standard output is compared
+// against the `expected` file
+fn convert_idris_test(path: &Path) -> Result<Conversion, ConvertError> {
+    let script = fs::read_to_string(path.join("run")).map_err(ConvertError::FailedToReadFile)?;
+
+    let mut warnings = vec![];
+    if script.contains("$1") || script.contains("${1}") {
+        warnings.push(ConvertWarning::ScriptArgument);
+    }
+
+    let mut fields = Fields::new();
+    fields.push("program", toml_string("sh"));
+    fields.push(
+        "program_arguments",
+        toml_string_array(&[String::from("run")]),
+    );
+    fields.push_separator();
+    fields.push("expected_stdout", toml_file_reference("expected"));
+
+    Ok(Conversion {
+        output_file: path.join("test.au.toml"),
+        content: fields.to_toml(),
+        warnings,
+    })
+}
+
+// HELPERS
+
+fn convert_status(warnings: &mut Vec<ConvertWarning>, status: &str) -> Option<i32> {
+    match status {
+        "success" => Some(0),
+        _ => match status.parse::<i32>() {
+            Ok(exit_code) => Some(exit_code),
+            Err(_) => {
+                warnings.push(ConvertWarning::UnsupportedStatus(status.to_owned()));
+                None
+            }
+        },
+    }
+}
+
+// `trycmd` supports `[..]` and `...` to match any text, and replaces some
+// values (e.g. `[CWD]`) before comparing
+fn contains_wildcards(expected_output: &str) -> bool {
+    const PLACEHOLDERS: [&str; 5] = ["[..]", "[EXE]", "[CWD]", "[ROOT]", "[ROOTURL]"];
+
+    PLACEHOLDERS.iter().any(|x| expected_output.contains(x))
+        || expected_output.lines().any(|x| x == "...")
+}
+
+fn file_stem(path: &Path, extension: &str) -> String {
+    let file_name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    file_name
+        .strip_suffix(extension)
+        .unwrap_or(&file_name)
+        .to_owned()
+}
+
+// WRITING CONFIG FILES
+
+// Fields are written in order, and a separator is written as an empty line
+struct Fields(Vec<Option<(String, String)>>);
+
+impl Fields {
+    fn new() -> Self {
+        Self(vec![])
+    }
+
+    fn push(&mut self, key: &str, value: String) {
+        self.0.push(Some((key.to_owned(), value)));
+    }
+
+    fn push_separator(&mut self) {
+        self.0.push(None);
+    }
+
+    fn to_toml(&self) -> String {
+        let mut output = String::new();
+
+        for field in &self.0 {
+            match field {
+                Some((key, value)) => output.push_str(&format!("{} = {}\n", key, value)),
+                None => output.push('\n'),
+            }
+        }
+
+        // Avoid empty lines at the start or end, or next to each other
+        let mut lines = output.lines().collect::<Vec<_>>();
+        lines.dedup_by(|a, b| a.is_empty() && b.is_empty());
+        let content = lines.join("\n");
+
+        format!("{}\n", content.trim_matches('\n'))
+    }
+}
+
+fn steps_to_toml(steps: Vec<Fields>) -> String {
+    if steps.len() == 1 {
+        return steps[0].to_toml();
+    }
+
+    steps
+        .iter()
+        .map(|fields| format!("[[steps]]\n{}", fields.to_toml()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn toml_file_reference(file_name: &str) -> String {
+    format!("{{ file = {} }}", toml_string(file_name))
+}
+
+fn toml_string_array(values: &[String]) -> String {
+    let values = values.iter().map(|x| toml_string(x)).collect::<Vec<_>>();
+    format!("[{}]", values.join(", "))
+}
+
+/// Strings containing newlines are written as multi-line strings
+fn toml_string(value: &str) -> String {
+    let is_multiline = value.contains('\n');
+    let mut output = String::new();
+
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '\n' if is_multiline => output.push('\n'),
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push('\t'),
+            '"' if !is_multiline || output.ends_with("\"\"") || chars.peek().is_none() => {
+                output.push_str("\\\"")
+            }
+            c if c.is_control() => output.push_str(&format!("\\u{:04X}", c as u32)),
+            c => output.push(c),
+        }
+    }
+
+    if is_multiline {
+        format!("\"\"\"\n{}\"\"\"", output)
+    } else {
+        format!("\"{}\"", output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse_trycmd_script() {
+        let content = indoc! {r#"
+            $ my-program --flag \
+            > "value"
+            Output
+            $ my-program --invalid
+            Error
+            ? 2
+
+        "#};
+
+        let commands = parse_trycmd_script(content);
+
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].line, "my-program --flag \"value\"");
+        assert_eq!(commands[0].status, None);
+        assert_eq!(commands[0].expected_output, "Output\n");
+        assert_eq!(commands[1].status, Some(String::from("2")));
+        assert_eq!(commands[1].expected_output, "Error\n");
+    }
+
+    #[test]
+    fn test_contains_wildcards() {
+        assert!(contains_wildcards("Finished in [..]s\n"));
+        assert!(contains_wildcards("First line\n...\n"));
+        assert!(!contains_wildcards("Loading...\n"));
+    }
+
+    #[test]
+    fn test_toml_string() {
+        let values = [
+            "",
+            "Hello \"world\"",
+            "C:\\path",
+            "Line 1\nLine 2\n",
+            "Quotes \"\"\" in multi-line\n\"",
+            "\u{1b}[31mRed\u{1b}[0m\n",
+        ];
+
+        for value in values {
+            let parsed: toml::Value = toml::from_str(&format!("value = {}", toml_string(value)))
+                .expect("The string should be valid TOML");
+            assert_eq!(parsed["value"].as_str(), Some(value));
+        }
+
+        assert_eq!(toml_string("a\nb"), "\"\"\"\na\nb\"\"\"");
+    }
+}
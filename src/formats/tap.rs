@@ -1,7 +1,26 @@
-use crate::test_result::{TestResult, ValueComparison};
-use crate::utils::string;
-use serde_yaml::{Number, Value};
+use crate::compare::{CompareMode, OutputDiff};
+use crate::test_case::{Assertion, RunError, TestCase, TestStep};
+use crate::test_hooks::HookFailure;
+use crate::test_result::{
+    Divergence, FailedAssertion, InteractionFailure, StepResult, TestResult, ValueComparison,
+};
+use crate::utils::process::CpuTime;
+use crate::utils::{duration, string};
+use serde_yaml::{Mapping, Number, Value};
 use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How long a test ran, which is reported when using `--verbose` or `--report-slowest`
+pub struct Timing {
+    pub duration: Duration,
+    pub cpu_time: Option<CpuTime>, // Only measured on Unix
+}
+
+/// Diagnostics about how a failing test was run, which are not part of the test result
+pub struct RunDetails {
+    pub timing: Option<Timing>, // Set when the timing of each test is reported
+    pub run: Option<u32>,       // Set when the test was run more than once
+}
 
 pub fn print_version() {
     println!("TAP version 14")
@@ -12,21 +31,57 @@ pub fn print_plan(start: usize, end: usize) {
 }
 
 pub fn print_ok(test_number: usize, message: &str, indent_level: usize) {
-    println!(
-        "ok     {:>indent$} - {}",
-        test_number,
-        message,
-        indent = indent_level
-    )
+    println!("{}", format_ok(test_number, message, indent_level))
+}
+
+/// Used when the timing of each test is reported
+pub fn print_ok_with_timing(
+    test_number: usize,
+    message: &str,
+    timing: &Timing,
+    indent_level: usize,
+) {
+    let mut diagnostics = BTreeMap::new();
+    insert_timing(&mut diagnostics, timing);
+    let diagnostics = format_diagnostics_map(diagnostics);
+
+    println!("{}", format_ok(test_number, message, indent_level));
+    println!("{}", format_diagnostics(&diagnostics));
+}
+
+/// Tests that passed on a retry are reported as `ok`, with the number of attempts as a diagnostic
+pub fn print_ok_flaky(
+    test_number: usize,
+    message: &str,
+    attempts: u32,
+    timing: Option<&Timing>,
+    indent_level: usize,
+) {
+    let mut flaky = Mapping::new();
+    flaky.insert(
+        Value::from("attempts"),
+        Value::Number(Number::from(attempts)),
+    );
+    let mut diagnostics = BTreeMap::from([(String::from("flaky"), Value::Mapping(flaky))]);
+    if let Some(timing) = timing {
+        insert_timing(&mut diagnostics, timing);
+    }
+    let diagnostics = format_diagnostics_map(diagnostics);
+
+    println!("{}", format_ok(test_number, message, indent_level));
+    println!("{}", format_diagnostics(&diagnostics));
 }
 
 pub fn print_not_ok(
     test_number: usize,
     message: &str,
+    test_case: &TestCase,
     test_result: &TestResult,
     indent_level: usize,
+    verbose: bool,
+    run_details: &RunDetails,
 ) {
-    let diagnostics = format_test_result(test_result);
+    let diagnostics = format_test_result(test_case, test_result, verbose, run_details);
     print_not_ok_diagnostics(test_number, message, &diagnostics, indent_level);
 }
 
@@ -37,62 +92,462 @@ pub fn print_not_ok_diagnostics(
     indent_level: usize,
 ) {
     println!(
+        "{}",
+        format_not_ok(test_number, message, diagnostics, indent_level)
+    )
+}
+
+pub fn print_diagnostics(diagnostics: &str) {
+    println!("{}", format_diagnostics(diagnostics));
+}
+
+/// Print the result of each step in a session as a subtest
+///
+/// The subtest should be followed by the `ok`/`not ok` line of the session itself.
+pub fn print_subtest(message: &str, test_case: &TestCase, test_result: &TestResult, verbose: bool) {
+    let number_of_steps = test_case.steps.len();
+    let indent_level = number_of_steps.to_string().len();
+
+    let mut lines = vec![format!("1..{}", number_of_steps)];
+    for (i, (step, step_result)) in test_case.steps.iter().zip(&test_result.steps).enumerate() {
+        let step_number = i + 1;
+        let name = step.name(i);
+
+        let line = match step_result {
+            Some(step_result) if step_result.is_success() => {
+                format_ok(step_number, &name, indent_level)
+            }
+            Some(step_result) => {
+                let diagnostics =
+                    format_diagnostics_map(format_step_result(step, step_result, verbose));
+                format_not_ok(step_number, &name, &diagnostics, indent_level)
+            }
+            None => format_ok(
+                step_number,
+                &format!("{} # SKIP Not run", name),
+                indent_level,
+            ),
+        };
+        lines.push(line);
+    }
+
+    println!("# Subtest: {}", message);
+    println!("{}", string::indent_by(4, &lines.join("\n")));
+}
+
+pub fn print_not_ok_run_error(
+    test_number: usize,
+    message: &str,
+    run_error: &RunError,
+    indent_level: usize,
+) {
+    let diagnostics = format_run_error(run_error);
+    print_not_ok_diagnostics(test_number, message, &diagnostics, indent_level);
+}
+
+pub fn print_bail_out(message: &str) {
+    println!("Bail out! {}", message)
+}
+
+pub fn print_comment(message: &str) {
+    println!("# {}", message)
+}
+
+// LINE FORMATTING
+
+fn format_ok(test_number: usize, message: &str, indent_level: usize) -> String {
+    format!(
+        "ok     {:>indent$} - {}",
+        test_number,
+        message,
+        indent = indent_level
+    )
+}
+
+fn format_not_ok(
+    test_number: usize,
+    message: &str,
+    diagnostics: &str,
+    indent_level: usize,
+) -> String {
+    let line = format!(
         "not ok {:>indent$} - {}",
         test_number,
         message,
         indent = indent_level
     );
 
-    if !diagnostics.is_empty() {
-        print_diagnostics(diagnostics)
+    if diagnostics.is_empty() {
+        line
+    } else {
+        format!("{}\n{}", line, format_diagnostics(diagnostics))
     }
 }
 
-pub fn print_diagnostics(diagnostics: &str) {
+fn format_diagnostics(diagnostics: &str) -> String {
     let code_block = format!("---\n{}...", diagnostics);
-    println!("{}", string::indent_by(2, &code_block));
-}
-
-#[allow(dead_code)]
-pub fn print_bail_out(message: &str) {
-    println!("Bail out! {}", message)
+    string::indent_by(2, &code_block)
 }
 
 // ERROR FORMATTING
 
-fn format_test_result(test_result: &TestResult) -> String {
+fn format_test_result(
+    test_case: &TestCase,
+    test_result: &TestResult,
+    verbose: bool,
+    run_details: &RunDetails,
+) -> String {
+    let mut diagnostics = BTreeMap::new();
+
+    if let Some(timing) = &run_details.timing {
+        insert_timing(&mut diagnostics, timing);
+    }
+
+    if let Some(run) = run_details.run {
+        diagnostics.insert(String::from("run"), Value::Number(Number::from(run)));
+    }
+
+    // The steps of a session are reported as a subtest
+    if !test_case.is_session {
+        for (step, step_result) in test_case.steps.iter().zip(&test_result.steps) {
+            if let Some(step_result) = step_result {
+                diagnostics.extend(format_step_result(step, step_result, verbose));
+            }
+        }
+    }
+
+    for (path, comparison) in &test_result.files {
+        if let ValueComparison::Diff { expected, got } = comparison {
+            diagnostics.insert(format!("file:{}", path), show_file_diff(expected, got));
+        }
+    }
+
+    if let Some(dir) = &test_result.dir {
+        if !dir.is_success() {
+            diagnostics.insert(format!("dir:{}", dir.path), show_dir_diff(&dir.files));
+        }
+    }
+
+    if let Some(divergence) = &test_result.divergence {
+        diagnostics.insert(
+            String::from("nondeterministic"),
+            show_divergence(test_case, divergence),
+        );
+    }
+
+    format_diagnostics_map(diagnostics)
+}
+
+fn format_step_result(
+    step: &TestStep,
+    step_result: &StepResult,
+    verbose: bool,
+) -> BTreeMap<String, Value> {
     let mut diagnostics = BTreeMap::new();
 
-    if let ValueComparison::Diff { expected, got } = &test_result.stdout {
-        diagnostics.insert("stdout", show_string_diff(expected, got));
+    if let Some(interaction_failure) = &step_result.interaction_failure {
+        diagnostics.insert(
+            String::from("interaction"),
+            show_interaction_failure(interaction_failure),
+        );
+    }
+
+    if let ValueComparison::Diff { expected, got } = &step_result.stdout {
+        diagnostics.insert(
+            String::from("stdout"),
+            show_output_diff(&step.stdout_compare, expected, got),
+        );
+    }
+
+    if let ValueComparison::Diff { expected, got } = &step_result.stderr {
+        diagnostics.insert(
+            String::from("stderr"),
+            show_output_diff(&step.stderr_compare, expected, got),
+        );
+    }
+
+    if verbose {
+        if let Some(unfiltered_stdout) = &step_result.unfiltered_stdout {
+            diagnostics.insert(
+                String::from("stdout-unfiltered"),
+                Value::from(unfiltered_stdout.as_str()),
+            );
+        }
+
+        if let Some(unfiltered_stderr) = &step_result.unfiltered_stderr {
+            diagnostics.insert(
+                String::from("stderr-unfiltered"),
+                Value::from(unfiltered_stderr.as_str()),
+            );
+        }
+    }
+
+    if let ValueComparison::Diff { expected, got } = step_result.exit_code {
+        diagnostics.insert(String::from("exit-code"), show_i32_diff(expected, got));
     }
 
-    if let ValueComparison::Diff { expected, got } = &test_result.stderr {
-        diagnostics.insert("stderr", show_string_diff(expected, got));
+    if let Some(max_duration) = step_result.exceeded_max_duration {
+        let mut mapping = Mapping::new();
+        mapping.insert(Value::from("max"), show_duration(max_duration));
+        mapping.insert(Value::from("got"), show_duration(step_result.duration));
+        diagnostics.insert(String::from("max-duration"), Value::Mapping(mapping));
     }
 
-    if let ValueComparison::Diff { expected, got } = test_result.exit_code {
-        diagnostics.insert("exit-code", show_i32_diff(expected, got));
+    diagnostics.extend(show_failed_assertions(&step_result.failed_assertions));
+
+    diagnostics
+}
+
+fn format_diagnostics_map(diagnostics: BTreeMap<String, Value>) -> String {
+    if diagnostics.is_empty() {
+        return String::new();
     }
 
     serde_yaml::to_string(&diagnostics)
         .unwrap_or_else(|_| String::from("Failed to convert to YAML\n"))
 }
 
-fn show_string_diff(expected: &String, got: &String) -> BTreeMap<&'static str, Value> {
-    show_diff(
-        Value::String(expected.to_owned()),
-        Value::String(got.to_owned()),
-    )
+fn format_run_error(run_error: &RunError) -> String {
+    let (key, failure) = match run_error {
+        RunError::SetupFailed(failure) => ("setup-failed", failure),
+        RunError::TeardownFailed(failure) => ("teardown-failed", failure),
+        RunError::FilterFailed(failure) => ("filter-failed", failure),
+        RunError::PtyNotSupported => {
+            return String::from(
+                "Running programs in a pseudo-terminal is not supported on this platform",
+            )
+        }
+        RunError::Killed => {
+            return String::from("message: The program was killed when the test run was stopped\n")
+        }
+        _ => return String::from("Failed to run test"),
+    };
+
+    let diagnostics = BTreeMap::from([(key, show_hook_failure(failure))]);
+
+    serde_yaml::to_string(&diagnostics)
+        .unwrap_or_else(|_| String::from("Failed to convert to YAML\n"))
+}
+
+// The exit code is `null` if the program was terminated by a signal
+fn show_interaction_failure(interaction_failure: &InteractionFailure) -> Value {
+    let mut mapping = Mapping::new();
+    match interaction_failure {
+        InteractionFailure::TimedOut {
+            expected,
+            timeout,
+            transcript,
+        } => {
+            mapping.insert(Value::from("expected"), Value::from(expected.as_str()));
+            mapping.insert(
+                Value::from("timeout"),
+                Value::from(format!("{:?}", timeout)),
+            );
+            mapping.insert(Value::from("transcript"), Value::from(transcript.as_str()));
+        }
+        InteractionFailure::Exited {
+            expected,
+            exit_code,
+            transcript,
+        } => {
+            mapping.insert(Value::from("expected"), Value::from(expected.as_str()));
+            mapping.insert(
+                Value::from("exit-code"),
+                exit_code.map_or(Value::Null, |x| Value::Number(Number::from(x))),
+            );
+            mapping.insert(Value::from("transcript"), Value::from(transcript.as_str()));
+        }
+        InteractionFailure::DidNotExit {
+            timeout,
+            transcript,
+        } => {
+            mapping.insert(
+                Value::from("exit-timeout"),
+                Value::from(format!("{:?}", timeout)),
+            );
+            mapping.insert(Value::from("transcript"), Value::from(transcript.as_str()));
+        }
+    }
+    Value::Mapping(mapping)
+}
+
+fn show_hook_failure(failure: &HookFailure) -> Value {
+    let mut mapping = Mapping::new();
+
+    match failure {
+        HookFailure::FailedToStart { command, error } => {
+            mapping.insert(Value::from("command"), Value::from(command.as_str()));
+            mapping.insert(Value::from("error"), Value::from(error.as_str()));
+        }
+        HookFailure::UnsuccessfulExit {
+            command,
+            exit_code,
+            stdout,
+            stderr,
+        } => {
+            let exit_code = exit_code.map_or(Value::Null, |x| Value::Number(Number::from(x)));
+
+            mapping.insert(Value::from("command"), Value::from(command.as_str()));
+            mapping.insert(Value::from("exit-code"), exit_code);
+            mapping.insert(Value::from("stdout"), Value::from(stdout.as_str()));
+            mapping.insert(Value::from("stderr"), Value::from(stderr.as_str()));
+        }
+    }
+
+    Value::Mapping(mapping)
 }
 
-fn show_i32_diff(expected: i32, got: i32) -> BTreeMap<&'static str, Value> {
+// Compare modes other than `exact` also describe the differences separately
+fn show_output_diff(compare: &CompareMode, expected: &str, got: &str) -> Value {
+    let mut mapping = Mapping::new();
+    mapping.insert(Value::from("expected"), Value::from(expected));
+    mapping.insert(Value::from("got"), Value::from(got));
+
+    let to_sequence =
+        |lines: Vec<String>| Value::Sequence(lines.into_iter().map(Value::from).collect());
+
+    match compare.diff(expected, got) {
+        OutputDiff::Lines => {}
+        OutputDiff::UnmatchedLines {
+            missing,
+            unexpected,
+        } => {
+            mapping.insert(Value::from("missing"), to_sequence(missing));
+            mapping.insert(Value::from("unexpected"), to_sequence(unexpected));
+        }
+        OutputDiff::Differences(differences)
+        | OutputDiff::NumericDifferences { differences, .. } => {
+            mapping.insert(Value::from("differences"), to_sequence(differences));
+        }
+    }
+
+    Value::Mapping(mapping)
+}
+
+// Grouped by field, e.g. `stdout-contains: { missing: ["Compiled"], got: "" }`
+fn show_failed_assertions(failed_assertions: &[FailedAssertion]) -> BTreeMap<String, Value> {
+    let mut groups = BTreeMap::<&str, (&str, Vec<Value>, &str)>::new();
+
+    for failed_assertion in failed_assertions {
+        let (key, kind, text) = match &failed_assertion.assertion {
+            Assertion::StdoutContains(text) => ("stdout-contains", "missing", text),
+            Assertion::StdoutNotContains(text) => ("stdout-not-contains", "found", text),
+            Assertion::StderrContains(text) => ("stderr-contains", "missing", text),
+            Assertion::StderrNotContains(text) => ("stderr-not-contains", "found", text),
+        };
+
+        groups
+            .entry(key)
+            .or_insert_with(|| (kind, vec![], &failed_assertion.output))
+            .1
+            .push(Value::from(text.as_str()));
+    }
+
+    groups
+        .into_iter()
+        .map(|(key, (kind, texts, output))| {
+            let mut mapping = Mapping::new();
+            mapping.insert(Value::from(kind), Value::Sequence(texts));
+            mapping.insert(Value::from("got"), Value::from(output));
+            (key.to_owned(), Value::Mapping(mapping))
+        })
+        .collect()
+}
+
+fn show_file_diff(expected: &Option<String>, got: &Option<String>) -> Value {
+    // Files that do not exist are shown as `null`
+    let to_value = |content: &Option<String>| content.clone().map_or(Value::Null, Value::String);
+
+    show_diff(to_value(expected), to_value(got))
+}
+
+fn show_dir_diff(files: &BTreeMap<String, ValueComparison<Option<Vec<u8>>>>) -> Value {
+    let mut mapping = Mapping::new();
+
+    // The content of binary files is not shown
+    let decode = |content: &Option<Vec<u8>>| match content {
+        Some(content) => str::from_utf8(content).ok().map(|x| Some(x.to_owned())),
+        None => Some(None),
+    };
+
+    for (path, comparison) in files {
+        if let ValueComparison::Diff { expected, got } = comparison {
+            let diff = match (decode(expected), decode(got)) {
+                (Some(expected), Some(got)) => show_file_diff(&expected, &got),
+                _ if expected.is_some() && got.is_some() => Value::from("binary files differ"),
+                _ => show_diff(
+                    expected
+                        .as_ref()
+                        .map_or(Value::Null, |_| Value::from("binary file")),
+                    got.as_ref()
+                        .map_or(Value::Null, |_| Value::from("binary file")),
+                ),
+            };
+            mapping.insert(Value::String(path.clone()), diff);
+        }
+    }
+
+    Value::Mapping(mapping)
+}
+
+fn show_i32_diff(expected: i32, got: i32) -> Value {
     show_diff(
         Value::Number(Number::from(expected)),
         Value::Number(Number::from(got)),
     )
 }
 
-fn show_diff(expected: Value, got: Value) -> BTreeMap<&'static str, Value> {
-    BTreeMap::from([("expected", expected), ("got", got)])
+// The output of the first run is shown as expected
+fn show_divergence(test_case: &TestCase, divergence: &Divergence) -> Value {
+    let step = &test_case.steps[divergence.step];
+
+    let mut mapping = Mapping::new();
+    mapping.insert(
+        Value::from("run"),
+        Value::Number(Number::from(divergence.run)),
+    );
+    if test_case.is_session {
+        mapping.insert(Value::from("step"), Value::from(step.name(divergence.step)));
+    }
+    if let ValueComparison::Diff { expected, got } = &divergence.stdout {
+        mapping.insert(
+            Value::from("stdout"),
+            show_output_diff(&step.stdout_compare, expected, got),
+        );
+    }
+    if let ValueComparison::Diff { expected, got } = &divergence.stderr {
+        mapping.insert(
+            Value::from("stderr"),
+            show_output_diff(&step.stderr_compare, expected, got),
+        );
+    }
+    if let ValueComparison::Diff { expected, got } = divergence.exit_code {
+        mapping.insert(Value::from("exit-code"), show_i32_diff(expected, got));
+    }
+
+    Value::Mapping(mapping)
+}
+
+// The times are in whole milliseconds, so that they are easy to process
+fn insert_timing(diagnostics: &mut BTreeMap<String, Value>, timing: &Timing) {
+    diagnostics.insert(String::from("duration-ms"), show_millis(timing.duration));
+    if let Some(cpu_time) = timing.cpu_time {
+        diagnostics.insert(String::from("user-time-ms"), show_millis(cpu_time.user));
+        diagnostics.insert(String::from("system-time-ms"), show_millis(cpu_time.system));
+    }
+}
+
+fn show_millis(value: Duration) -> Value {
+    Value::Number(Number::from(value.as_millis() as u64))
+}
+
+fn show_duration(value: Duration) -> Value {
+    Value::from(duration::format_duration(value))
+}
+
+fn show_diff(expected: Value, got: Value) -> Value {
+    let mut mapping = Mapping::new();
+    mapping.insert(Value::from("expected"), expected);
+    mapping.insert(Value::from("got"), got);
+    Value::Mapping(mapping)
 }
@@ -1,9 +1,14 @@
 use crate::ascii_tree;
 pub use crate::ascii_tree::Tree::{self, Leaf, Node};
-use crate::test_result::{TestResult, ValueComparison};
-use crate::utils::string;
+use crate::compare::{CompareMode, OutputDiff};
+use crate::test_case::{Assertion, RunError, TestCase, TestStep};
+use crate::test_hooks::HookFailure;
+use crate::test_result::{Divergence, InteractionFailure, StepResult, TestResult, ValueComparison};
+use crate::utils::{duration, string};
 use colored::Colorize;
+use std::collections::BTreeMap;
 use std::fmt::Error;
+use std::ops::Range;
 
 pub fn draw_tree(tree: &Tree) -> Result<String, Error> {
     let mut output = String::new();
@@ -23,33 +28,377 @@ pub fn text_block(content: &str) -> String {
 
 // ERROR FORMATTING
 
-pub fn nodes_from_test_result(test_result: &TestResult) -> Vec<Tree> {
+pub fn nodes_from_test_result(
+    test_case: &TestCase,
+    test_result: &TestResult,
+    verbose: bool,
+) -> Vec<Tree> {
     let mut categories = vec![];
 
-    if let ValueComparison::Diff { expected, got } = &test_result.stdout {
+    if test_case.is_session {
+        for (i, (step, step_result)) in test_case.steps.iter().zip(&test_result.steps).enumerate() {
+            let name = step.name(i);
+
+            categories.push(match step_result {
+                Some(step_result) if step_result.is_success() => Leaf(vec![format!("✅ {}", name)]),
+                Some(step_result) => Node(
+                    format!("❌ {}", name),
+                    nodes_from_step_result(step, step_result, verbose),
+                ),
+                None => Leaf(vec![format!("➖ {} (Not run)", name)]),
+            });
+        }
+    } else {
+        for (step, step_result) in test_case.steps.iter().zip(&test_result.steps) {
+            if let Some(step_result) = step_result {
+                categories.extend(nodes_from_step_result(step, step_result, verbose));
+            }
+        }
+    }
+
+    for (path, comparison) in &test_result.files {
+        if let ValueComparison::Diff { expected, got } = comparison {
+            categories.push(Node(
+                format!("File '{}'", path),
+                show_file_diff(expected, got),
+            ));
+        }
+    }
+
+    if let Some(dir) = &test_result.dir {
+        if !dir.is_success() {
+            categories.push(Node(
+                format!("Directory '{}'", dir.path),
+                show_dir_diff(&dir.files),
+            ));
+        }
+    }
+
+    if let Some(divergence) = &test_result.divergence {
+        let heading = if test_case.is_session {
+            let step_name = test_case.steps[divergence.step].name(divergence.step);
+            format!("Run {} differed from run 1 ({})", divergence.run, step_name)
+        } else {
+            format!("Run {} differed from run 1", divergence.run)
+        };
+        categories.push(Node(heading, show_divergence(test_case, divergence)));
+    }
+
+    categories
+}
+
+// The output of the first run is shown as expected
+fn show_divergence(test_case: &TestCase, divergence: &Divergence) -> Vec<Tree> {
+    let step = &test_case.steps[divergence.step];
+    let mut categories = vec![];
+
+    if let ValueComparison::Diff { expected, got } = &divergence.stdout {
+        categories.push(Node(
+            String::from("Standard output"),
+            show_output_diff(&step.stdout_compare, expected, got),
+        ));
+    }
+
+    if let ValueComparison::Diff { expected, got } = &divergence.stderr {
+        categories.push(Node(
+            String::from("Standard error"),
+            show_output_diff(&step.stderr_compare, expected, got),
+        ));
+    }
+
+    if let ValueComparison::Diff { expected, got } = divergence.exit_code {
+        categories.push(Node(
+            String::from("Exit code"),
+            show_i32_diff(expected, got),
+        ));
+    }
+
+    categories
+}
+
+fn nodes_from_step_result(step: &TestStep, step_result: &StepResult, verbose: bool) -> Vec<Tree> {
+    let mut categories = vec![];
+
+    if let Some(interaction_failure) = &step_result.interaction_failure {
+        categories.push(Node(
+            String::from("Interaction"),
+            show_interaction_failure(interaction_failure),
+        ));
+    }
+
+    if let ValueComparison::Diff { expected, got } = &step_result.stdout {
         categories.push(Node(
             String::from("Standard output"),
-            show_string_diff(expected, got),
+            show_output_diff(&step.stdout_compare, expected, got),
         ));
     }
 
-    if let ValueComparison::Diff { expected, got } = &test_result.stderr {
+    if let ValueComparison::Diff { expected, got } = &step_result.stderr {
         categories.push(Node(
             String::from("Standard error"),
-            show_string_diff(expected, got),
+            show_output_diff(&step.stderr_compare, expected, got),
         ));
     }
 
-    if let ValueComparison::Diff { expected, got } = test_result.exit_code {
+    if verbose {
+        if let Some(unfiltered_stdout) = &step_result.unfiltered_stdout {
+            categories.push(Node(
+                String::from("Unfiltered standard output"),
+                vec![Leaf(string_to_lines(&text_block(unfiltered_stdout)))],
+            ));
+        }
+
+        if let Some(unfiltered_stderr) = &step_result.unfiltered_stderr {
+            categories.push(Node(
+                String::from("Unfiltered standard error"),
+                vec![Leaf(string_to_lines(&text_block(unfiltered_stderr)))],
+            ));
+        }
+    }
+
+    if let ValueComparison::Diff { expected, got } = step_result.exit_code {
         categories.push(Node(
             String::from("Exit code"),
             show_i32_diff(expected, got),
         ));
     }
 
+    if let Some(max_duration) = step_result.exceeded_max_duration {
+        categories.push(Node(
+            String::from("Duration"),
+            show_single_line_diff(
+                format!("At most {}", duration::format_duration(max_duration)),
+                duration::format_duration(step_result.duration),
+            ),
+        ));
+    }
+
+    for failed_assertion in &step_result.failed_assertions {
+        categories.push(Node(
+            show_assertion(&failed_assertion.assertion),
+            vec![Leaf(string_to_lines(&format!(
+                "Got\n{}",
+                text_block(&failed_assertion.output)
+            )))],
+        ));
+    }
+
     categories
 }
 
+fn show_assertion(assertion: &Assertion) -> String {
+    match assertion {
+        Assertion::StdoutContains(text) => format!("Standard output should contain {:?}", text),
+        Assertion::StdoutNotContains(text) => {
+            format!("Standard output should not contain {:?}", text)
+        }
+        Assertion::StderrContains(text) => format!("Standard error should contain {:?}", text),
+        Assertion::StderrNotContains(text) => {
+            format!("Standard error should not contain {:?}", text)
+        }
+    }
+}
+
+pub fn nodes_from_run_error(run_error: &RunError) -> Vec<Tree> {
+    match run_error {
+        RunError::SetupFailed(failure) => {
+            vec![Node(
+                String::from("Setup failed"),
+                show_hook_failure(failure),
+            )]
+        }
+        RunError::TeardownFailed(failure) => {
+            vec![Node(
+                String::from("Teardown failed"),
+                show_hook_failure(failure),
+            )]
+        }
+        RunError::FilterFailed(failure) => {
+            vec![Node(
+                String::from("Filter failed"),
+                show_hook_failure(failure),
+            )]
+        }
+        RunError::PtyNotSupported => vec![Leaf(vec![String::from(
+            "Running programs in a pseudo-terminal is not supported on this platform",
+        )])],
+        RunError::Killed => vec![Leaf(vec![String::from(
+            "The program was killed when the test run was stopped",
+        )])],
+        _ => vec![Leaf(vec![String::from("Failed to run test")])],
+    }
+}
+
+fn show_interaction_failure(interaction_failure: &InteractionFailure) -> Vec<Tree> {
+    let (heading, expected, transcript) = match interaction_failure {
+        InteractionFailure::TimedOut {
+            expected,
+            timeout,
+            transcript,
+        } => (
+            format!("Expected within {:?}", timeout),
+            expected,
+            transcript,
+        ),
+        InteractionFailure::Exited {
+            expected,
+            exit_code,
+            transcript,
+        } => {
+            let heading = match exit_code {
+                Some(exit_code) => format!(
+                    "The program exited with code {} before the expected text appeared",
+                    exit_code
+                ),
+                None => String::from(
+                    "The program was terminated by a signal before the expected text appeared",
+                ),
+            };
+            (heading, expected, transcript)
+        }
+        InteractionFailure::DidNotExit {
+            timeout,
+            transcript,
+        } => {
+            return vec![
+                Leaf(vec![format!(
+                    "The program did not exit within {:?} after the interaction",
+                    timeout
+                )]),
+                show_transcript(transcript),
+            ];
+        }
+    };
+
+    vec![
+        Leaf(string_to_lines(&format!(
+            "{}\n{}",
+            heading,
+            text_block(expected)
+        ))),
+        show_transcript(transcript),
+    ]
+}
+
+fn show_transcript(transcript: &str) -> Tree {
+    Leaf(string_to_lines(&format!(
+        "Transcript\n{}",
+        text_block(transcript)
+    )))
+}
+
+fn show_hook_failure(failure: &HookFailure) -> Vec<Tree> {
+    match failure {
+        HookFailure::FailedToStart { command, error } => vec![
+            Node(String::from("Command"), vec![Leaf(vec![command.clone()])]),
+            Node(String::from("Error"), vec![Leaf(vec![error.clone()])]),
+        ],
+        HookFailure::UnsuccessfulExit {
+            command,
+            exit_code,
+            stdout,
+            stderr,
+        } => {
+            let exit_code = exit_code.map_or(String::from("None"), |x| x.to_string());
+
+            vec![
+                Node(String::from("Command"), vec![Leaf(vec![command.clone()])]),
+                Node(String::from("Exit code"), vec![Leaf(vec![exit_code])]),
+                Leaf(string_to_lines(&format!(
+                    "Standard output\n{}",
+                    text_block(stdout)
+                ))),
+                Leaf(string_to_lines(&format!(
+                    "Standard error\n{}",
+                    text_block(stderr)
+                ))),
+            ]
+        }
+    }
+}
+
+fn show_output_diff(compare: &CompareMode, expected: &str, got: &str) -> Vec<Tree> {
+    let (missing, unexpected) = match compare.diff(expected, got) {
+        OutputDiff::Lines => return show_string_diff(expected, got),
+        OutputDiff::UnmatchedLines {
+            missing,
+            unexpected,
+        } => (missing, unexpected),
+        OutputDiff::Differences(differences) => {
+            let differences = differences
+                .into_iter()
+                .map(|x| Leaf(string_to_lines(&x)))
+                .collect();
+
+            return vec![
+                Leaf(string_to_lines(&format!(
+                    "Expected\n{}",
+                    text_block(expected)
+                ))),
+                Leaf(string_to_lines(&format!("Got\n{}", text_block(got)))),
+                Node(String::from("Differences"), differences),
+            ];
+        }
+        OutputDiff::NumericDifferences {
+            differences,
+            expected_numbers,
+            got_numbers,
+        } => {
+            let differences = differences
+                .into_iter()
+                .map(|x| Leaf(string_to_lines(&x)))
+                .collect();
+
+            // The numbers outside the tolerance are highlighted
+            return vec![
+                Leaf(string_to_lines(&format!(
+                    "Expected\n{}",
+                    text_block(&highlight_ranges(expected, &expected_numbers))
+                ))),
+                Leaf(string_to_lines(&format!(
+                    "Got\n{}",
+                    text_block(&highlight_ranges(got, &got_numbers))
+                ))),
+                Node(String::from("Differences"), differences),
+            ];
+        }
+    };
+
+    // Only the lines that are missing or unexpected are shown in the diff
+    let mut diff_output = String::new();
+    for line in missing {
+        diff_output.push_str(&format!("{}\n", (String::from("-") + &line).red()));
+    }
+    for line in unexpected {
+        diff_output.push_str(&format!("{}\n", (String::from("+") + &line).green()));
+    }
+
+    vec![
+        Leaf(string_to_lines(&format!(
+            "Expected\n{}",
+            text_block(expected)
+        ))),
+        Leaf(string_to_lines(&format!("Got\n{}", text_block(got)))),
+        Leaf(string_to_lines(&format!(
+            "Diff\n{}",
+            text_block(&diff_output)
+        ))),
+    ]
+}
+
+// The ranges must be sorted and must not overlap
+fn highlight_ranges(text: &str, ranges: &[Range<usize>]) -> String {
+    let mut result = String::new();
+    let mut end = 0;
+    for range in ranges {
+        result.push_str(&text[end..range.start]);
+        result.push_str(&text[range.clone()].red().bold().to_string());
+        end = range.end;
+    }
+    result.push_str(&text[end..]);
+    result
+}
+
 fn show_string_diff(expected: &str, got: &str) -> Vec<Tree> {
     let expected_lines = string_to_lines(&format!("Expected\n{}", text_block(expected)));
     let got_lines = string_to_lines(&format!("Got\n{}", text_block(got)));
@@ -72,6 +421,73 @@ fn show_string_diff(expected: &str, got: &str) -> Vec<Tree> {
     vec![Leaf(expected_lines), Leaf(got_lines), Leaf(diff_lines)]
 }
 
+fn show_file_diff(expected: &Option<String>, got: &Option<String>) -> Vec<Tree> {
+    if let (Some(expected), Some(got)) = (expected, got) {
+        return show_string_diff(expected, got);
+    }
+
+    vec![
+        show_file_content("Expected", expected),
+        show_file_content("Got", got),
+    ]
+}
+
+fn show_dir_diff(files: &BTreeMap<String, ValueComparison<Option<Vec<u8>>>>) -> Vec<Tree> {
+    let mut nodes = vec![];
+
+    for (path, comparison) in files {
+        if let ValueComparison::Diff { expected, got } = comparison {
+            let node = match (expected, got) {
+                (Some(expected), Some(got)) => Node(
+                    format!("Changed file '{}'", path),
+                    match (str::from_utf8(expected), str::from_utf8(got)) {
+                        (Ok(expected), Ok(got)) => show_string_diff(expected, got),
+                        _ => vec![Leaf(vec![String::from("Binary files differ")])],
+                    },
+                ),
+                (Some(expected), None) => Node(
+                    format!("Missing file '{}'", path),
+                    vec![show_binary_file_content("Expected", expected)],
+                ),
+                (None, Some(got)) => Node(
+                    format!("Unexpected file '{}'", path),
+                    vec![show_binary_file_content("Got", got)],
+                ),
+                (None, None) => continue,
+            };
+
+            nodes.push(node);
+        }
+    }
+
+    nodes
+}
+
+// The content of binary files is not shown
+fn show_binary_file_content(heading: &str, content: &[u8]) -> Tree {
+    match str::from_utf8(content) {
+        Ok(content) => show_file_content(heading, &Some(content.to_owned())),
+        Err(_) => Node(
+            heading.to_owned(),
+            vec![Leaf(vec![String::from("(Binary file)")])],
+        ),
+    }
+}
+
+fn show_file_content(heading: &str, content: &Option<String>) -> Tree {
+    match content {
+        Some(content) => Leaf(string_to_lines(&format!(
+            "{}\n{}",
+            heading,
+            text_block(content)
+        ))),
+        None => Node(
+            heading.to_owned(),
+            vec![Leaf(vec![String::from("(File does not exist)")])],
+        ),
+    }
+}
+
 fn string_to_lines(str: &str) -> Vec<String> {
     str.lines().map(|x| x.to_owned()).collect()
 }
@@ -167,4 +583,14 @@ mod tests {
 
         assert_eq!(text_block("line 1\n\nline 3\n"), expected);
     }
+
+    #[test]
+    fn test_highlight_ranges() {
+        colored::control::set_override(true);
+
+        assert_eq!(
+            highlight_ranges("x = 1.5, y = 2\n", &[4..7, 13..14]),
+            "x = \u{1b}[1;31m1.5\u{1b}[0m, y = \u{1b}[1;31m2\u{1b}[0m\n"
+        );
+    }
 }
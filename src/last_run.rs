@@ -0,0 +1,78 @@
+//! The results of the previous test runs, stored in `.aureum/last-run.json` in
+//! the current directory (next to `aureum.toml`). Used by `--failed` and
+//! `--failed-first`.
+
+use crate::test_runner::RunResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+pub const DIR_NAME: &str = ".aureum";
+pub const FILE_NAME: &str = "last-run.json";
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct LastRun {
+    pub tests: BTreeMap<String, TestStatus>, // Indexed by `TestCase::id()`
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum TestStatus {
+    Passed,
+    Failed,
+}
+
+impl LastRun {
+    pub fn is_failed(&self, test_id: &str) -> bool {
+        self.tests.get(test_id) == Some(&TestStatus::Failed)
+    }
+
+    /// Tests that were not part of the run (or were not run because the run
+    /// was stopped early) keep their previous status
+    pub fn update(&mut self, run_results: &[RunResult]) {
+        for run_result in run_results {
+            let status = if run_result.is_success() {
+                TestStatus::Passed
+            } else if run_result.is_failure() {
+                TestStatus::Failed
+            } else {
+                continue;
+            };
+
+            self.tests.insert(run_result.test_case.id(), status);
+        }
+    }
+}
+
+pub fn file_path(dir: &Path) -> PathBuf {
+    dir.join(DIR_NAME).join(FILE_NAME)
+}
+
+/// Returns `None` if no tests have been run yet
+pub fn read_last_run(dir: &Path) -> io::Result<Option<LastRun>> {
+    let content = match fs::read_to_string(file_path(dir)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The file is replaced in one step, so that runs in parallel never read a
+/// partially written file
+pub fn write_last_run(dir: &Path, last_run: &LastRun) -> io::Result<()> {
+    let state_dir = dir.join(DIR_NAME);
+    fs::create_dir_all(&state_dir)?;
+
+    let content = serde_json::to_string_pretty(last_run).map_err(io::Error::other)?;
+    let mut file = NamedTempFile::new_in(&state_dir)?;
+    file.write_all((content + "\n").as_bytes())?;
+    file.persist(file_path(dir)).map_err(|err| err.error)?;
+    Ok(())
+}
@@ -1,15 +1,28 @@
+pub mod accept;
 mod ascii_tree;
+pub mod cancellation;
+pub mod compare;
+pub mod convert;
 pub mod formats {
     pub mod tap;
     pub mod tree;
 }
+pub mod last_run;
+mod markdown;
+pub mod project_config;
+#[cfg(unix)]
+mod pty;
 pub mod test_case;
+pub mod test_hooks;
 pub mod test_id;
 pub mod test_id_container;
 pub mod test_result;
 pub mod test_runner;
 pub mod toml_config;
 pub mod utils {
+    pub mod duration;
     pub mod file;
+    pub mod process;
+    pub mod source;
     pub mod string;
 }
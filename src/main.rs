@@ -1,24 +1,52 @@
 mod cli;
 
-use aureum::test_runner::{ReportConfig, ReportFormat};
+use aureum::convert;
+use aureum::last_run::{self, LastRun};
+use aureum::project_config::{self, ProjectConfig};
+use aureum::test_case::TestCase;
+use aureum::test_id::TestId;
+use aureum::test_runner::{ReportConfig, ReportFormat, ReportOrder, RunConfig, RunResult};
+use aureum::toml_config::{ProgramPath, Requirement, TestDefinition};
+use aureum::utils::file as file_utils;
 use cli::file;
 use cli::report;
-use cli::{Args, OutputFormat};
+use cli::watch;
+use cli::{Args, Command, OutputFormat, StreamOrder};
+use relative_path::RelativePathBuf;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::thread;
 
 const TEST_FAILURE_EXIT_CODE: i32 = 1;
 const INVALID_USER_INPUT_EXIT_CODE: i32 = 2;
+const NO_STATE_ENV_VAR: &str = "AUREUM_NO_STATE";
 
 fn main() {
     let args = cli::parse();
 
+    if let Some(Command::Convert { paths }) = &args.command {
+        convert_files(paths);
+        return;
+    }
+
     let current_dir = env::current_dir().expect("Current directory must be available");
 
-    let source_files = file::expand_test_paths(&args.paths, &current_dir)
-        .keys()
-        .cloned()
-        .collect::<Vec<_>>();
+    let project_config = match project_config::read_project_config(&current_dir) {
+        Ok(project_config) => project_config,
+        Err(error) => {
+            report::print_toml_config_error(
+                RelativePathBuf::from(project_config::FILE_NAME),
+                error,
+            );
+            exit(INVALID_USER_INPUT_EXIT_CODE);
+        }
+    };
+
+    let source_files = find_source_files(&args, &current_dir);
 
     if source_files.is_empty() {
         report::print_no_config_files();
@@ -29,45 +57,251 @@ fn main() {
         report::print_files_found(&source_files);
     }
 
-    let mut all_test_cases = vec![];
-    let mut any_failed_configs = false;
+    let configs = source_files
+        .into_iter()
+        .map(|source_file| {
+            let config = load_config(&args, &source_file);
+            (source_file, config)
+        })
+        .collect::<BTreeMap<_, _>>();
 
-    for source_file in source_files {
-        match aureum::toml_config::parse_toml_config(&source_file) {
-            Ok(config) => {
-                let any_issues = report::any_issues_in_toml_config(&config);
-                if any_issues || args.verbose {
-                    report::print_config_details(
-                        source_file,
-                        &config,
-                        args.verbose,
-                        args.hide_absolute_paths,
-                    );
+    let mut last_run = load_last_run(&args, &current_dir, project_config.is_some());
+    let project_config = project_config.unwrap_or_default();
+    let test_cases = select_test_cases(&args, last_run.as_ref(), all_test_cases(&configs));
 
-                    if any_issues {
-                        any_failed_configs = true;
-                    }
-                }
+    if args.watch {
+        watch_tests(
+            &args,
+            &current_dir,
+            &project_config,
+            last_run,
+            configs,
+            test_cases,
+        );
+    }
 
-                all_test_cases.extend(config.tests.into_values().filter_map(|x| x.test_case.ok()));
-            }
-            Err(error) => {
-                report::print_toml_config_error(source_file, error);
-                any_failed_configs = true;
-            }
+    let any_failed_configs = configs.values().any(|x| x.has_errors);
+
+    if !run_tests(
+        &args,
+        &current_dir,
+        &project_config,
+        last_run.as_mut(),
+        test_cases,
+        any_failed_configs,
+    ) {
+        exit(TEST_FAILURE_EXIT_CODE)
+    }
+}
+
+fn find_source_files(args: &Args, current_dir: &Path) -> Vec<RelativePathBuf> {
+    match try_find_source_files(args, current_dir) {
+        Ok(source_files) => source_files,
+        Err((path, error)) => {
+            report::print_locate_file_error(&path, &error);
+            exit(INVALID_USER_INPUT_EXIT_CODE);
+        }
+    }
+}
+
+fn try_find_source_files(
+    args: &Args,
+    current_dir: &Path,
+) -> Result<Vec<RelativePathBuf>, (String, file::LocateFileError)> {
+    file::expand_test_paths(&args.paths, current_dir, args.markdown)
+        .map(|files| files.into_keys().collect())
+}
+
+// The tests of a config file. Tests with validation errors are kept, since
+// they may become valid when one of their dependencies changes.
+struct LoadedConfig {
+    tests: BTreeMap<TestId, LoadedTest>,
+    has_errors: bool,
+}
+
+struct LoadedTest {
+    test_case: Option<TestCase>,
+    definition: TestDefinition, // Used by `--watch` to find the tests that changed
+    dependencies: BTreeSet<PathBuf>, // External files and programs, used by `--watch`
+}
+
+fn all_test_cases(configs: &BTreeMap<RelativePathBuf, LoadedConfig>) -> Vec<TestCase> {
+    configs
+        .values()
+        .flat_map(|x| x.tests.values())
+        .filter_map(|x| x.test_case.clone())
+        .collect()
+}
+
+// Returns `None` if the results are not stored. They are only stored for
+// projects (with an `aureum.toml`), or when `--failed` or `--failed-first` is
+// used, so that other runs (e.g. in CI) do not write to the current directory.
+fn load_last_run(args: &Args, current_dir: &Path, is_project: bool) -> Option<LastRun> {
+    // Set by tests that run `aureum` themselves, so that the nested runs do
+    // not store their results next to the tests
+    if args.no_state || env::var_os(NO_STATE_ENV_VAR).is_some() {
+        if args.failed || args.failed_first {
+            report::print_no_state_conflict(NO_STATE_ENV_VAR);
+            exit(INVALID_USER_INPUT_EXIT_CODE);
         }
+        return None;
+    }
+
+    if !(is_project || args.failed || args.failed_first) {
+        return None;
+    }
+
+    let path = last_run::file_path(Path::new("")); // Relative to the current directory
+    match last_run::read_last_run(current_dir) {
+        Ok(Some(last_run)) => Some(last_run),
+        Ok(None) if args.failed => {
+            report::print_no_last_run(&path);
+            exit(INVALID_USER_INPUT_EXIT_CODE);
+        }
+        Ok(None) => Some(LastRun::default()),
+        Err(err) => {
+            report::print_last_run_read_error(&path, err);
+            exit(INVALID_USER_INPUT_EXIT_CODE);
+        }
+    }
+}
+
+// Used by `--failed` and `--failed-first`, which require a `last_run`
+fn select_test_cases(
+    args: &Args,
+    last_run: Option<&LastRun>,
+    test_cases: Vec<TestCase>,
+) -> Vec<TestCase> {
+    let Some(last_run) = last_run else {
+        return test_cases;
+    };
+
+    if args.failed {
+        test_cases
+            .into_iter()
+            .filter(|x| last_run.is_failed(&x.id()))
+            .collect()
+    } else if args.failed_first {
+        let (mut failed_tests, other_tests): (Vec<_>, Vec<_>) = test_cases
+            .into_iter()
+            .partition(|x| last_run.is_failed(&x.id()));
+        failed_tests.extend(other_tests);
+        failed_tests
+    } else {
+        test_cases
+    }
+}
+
+// Issues in the config file are reported when it is loaded
+fn load_config(args: &Args, source_file: &RelativePathBuf) -> LoadedConfig {
+    let config = match aureum::toml_config::parse_config(source_file) {
+        Ok(config) => config,
+        Err(error) => {
+            report::print_toml_config_error(source_file.clone(), error);
+            return LoadedConfig {
+                tests: BTreeMap::new(),
+                has_errors: true,
+            };
+        }
+    };
+
+    let has_errors = report::any_issues_in_toml_config(&config);
+    if has_errors || args.verbose {
+        report::print_config_details(
+            source_file.clone(),
+            &config,
+            args.verbose,
+            args.hide_absolute_paths,
+        );
+    }
+
+    // Tests are not run without the setup in `[once]`
+    let once_is_valid = config.once_details.validation_errors.is_empty();
+
+    let source_dir = file_utils::parent_dir(source_file).to_logical_path(".");
+    let tests = config
+        .tests
+        .into_iter()
+        .map(|(test_id, test_details)| {
+            let external_files = test_details
+                .requirements
+                .into_iter()
+                .filter_map(|requirement| match requirement {
+                    Requirement::ExternalFile(path) => Some(source_dir.join(path)),
+                    Requirement::EnvVar(_) => None,
+                });
+            let programs = test_details
+                .program_paths
+                .into_iter()
+                .filter_map(|program_path| match program_path {
+                    ProgramPath::ResolvedPath {
+                        requested_path: _,
+                        resolved_path,
+                    } => Some(resolved_path),
+                    _ => None,
+                });
+
+            let loaded_test = LoadedTest {
+                test_case: test_details.test_case.ok().filter(|_| once_is_valid),
+                definition: test_details.definition,
+                dependencies: external_files.chain(programs).collect(),
+            };
+            (test_id, loaded_test)
+        })
+        .collect();
+
+    LoadedConfig { tests, has_errors }
+}
+
+// Returns whether all tests passed
+fn run_tests(
+    args: &Args,
+    current_dir: &Path,
+    project_config: &ProjectConfig,
+    last_run: Option<&mut LastRun>,
+    mut test_cases: Vec<TestCase>,
+    any_failed_configs: bool,
+) -> bool {
+    let seed = get_seed(args);
+    if let Some(seed) = seed {
+        fastrand::Rng::with_seed(seed).shuffle(&mut test_cases);
     }
 
     let report_config = ReportConfig {
-        number_of_tests: all_test_cases.len(),
-        format: get_report_format(&args),
+        number_of_tests: test_cases.len(),
+        format: get_report_format(args),
+        verbose: args.verbose,
+        order: match args.stream_order {
+            StreamOrder::Declaration => ReportOrder::Declaration,
+            StreamOrder::Completion => ReportOrder::Completion,
+        },
+        report_slowest: args.report_slowest,
+        seed,
     };
 
-    let run_results = aureum::test_runner::run_test_cases(
-        &report_config,
-        &all_test_cases,
-        args.run_tests_in_parallel,
-    );
+    let run_config = RunConfig {
+        jobs: get_jobs(args, project_config),
+        retries: args.retries,
+        repeat: args.repeat,
+        max_failures: if args.fail_fast {
+            Some(NonZeroUsize::MIN)
+        } else {
+            args.max_failures
+        },
+    };
+
+    let run_results = aureum::test_runner::run_test_cases(&report_config, &run_config, &test_cases);
+
+    if args.accept {
+        accept_run_results(&run_results);
+    }
+
+    if let Some(last_run) = last_run {
+        last_run.update(&run_results);
+        if let Err(err) = last_run::write_last_run(current_dir, last_run) {
+            report::print_last_run_error(&last_run::file_path(Path::new("")), err);
+        }
+    }
 
     if any_failed_configs {
         eprintln!("Some config files contain errors (See above)");
@@ -75,11 +309,177 @@ fn main() {
 
     let all_tests_passed = run_results.iter().all(|t| t.is_success());
 
-    if any_failed_configs || !all_tests_passed {
+    !any_failed_configs && all_tests_passed
+}
+
+// When a config file or a file that its tests depend on changes, it is
+// reloaded. Only the tests that were added or changed in the config file, or
+// that depend on a changed file, are run again.
+fn watch_tests(
+    args: &Args,
+    current_dir: &Path,
+    project_config: &ProjectConfig,
+    mut last_run: Option<LastRun>,
+    mut configs: BTreeMap<RelativePathBuf, LoadedConfig>,
+    mut test_cases: Vec<TestCase>,
+) -> ! {
+    let mut any_failed_configs = configs.values().any(|x| x.has_errors);
+
+    loop {
+        // Taken before the run, so that changes made while the tests are
+        // running are not missed
+        let snapshot = watch::Snapshot::new(watched_paths(&configs));
+
+        run_tests(
+            args,
+            current_dir,
+            project_config,
+            last_run.as_mut(),
+            test_cases,
+            any_failed_configs,
+        );
+        report::print_watching();
+
+        let (source_files, changed_paths) = loop {
+            watch::wait();
+
+            // A directory that cannot be searched at the moment (e.g. because
+            // it is being removed) is searched again at the next check
+            let Ok(source_files) = try_find_source_files(args, current_dir) else {
+                continue;
+            };
+            let changed_paths = snapshot.changed_paths();
+            if !changed_paths.is_empty() || !source_files.iter().eq(configs.keys()) {
+                break (source_files, changed_paths);
+            }
+        };
+
+        watch::clear_screen();
+
+        let mut previous_configs = mem::take(&mut configs);
+        test_cases = vec![];
+        any_failed_configs = false;
+
+        for source_file in source_files {
+            let previous_tests = match previous_configs.remove(&source_file) {
+                Some(config) if !changed_paths.contains(&source_file.to_logical_path(".")) => {
+                    let is_affected = config
+                        .tests
+                        .values()
+                        .any(|x| !x.dependencies.is_disjoint(&changed_paths));
+
+                    if !is_affected {
+                        configs.insert(source_file, config);
+                        continue;
+                    }
+
+                    config.tests
+                }
+                Some(config) => config.tests,
+                None => BTreeMap::new(),
+            };
+
+            let config = load_config(args, &source_file);
+            test_cases.extend(
+                config
+                    .tests
+                    .iter()
+                    .filter(|(test_id, test)| match previous_tests.get(test_id) {
+                        Some(previous_test) => {
+                            previous_test.definition != test.definition
+                                || !previous_test.dependencies.is_disjoint(&changed_paths)
+                        }
+                        None => true,
+                    })
+                    .filter_map(|(_, x)| x.test_case.clone()),
+            );
+            any_failed_configs |= config.has_errors;
+            configs.insert(source_file, config);
+        }
+    }
+}
+
+fn watched_paths(configs: &BTreeMap<RelativePathBuf, LoadedConfig>) -> BTreeSet<PathBuf> {
+    let mut paths = BTreeSet::new();
+
+    for (source_file, config) in configs {
+        paths.insert(source_file.to_logical_path("."));
+        for test in config.tests.values() {
+            paths.extend(test.dependencies.iter().cloned());
+        }
+    }
+
+    paths
+}
+
+fn accept_run_results(run_results: &[RunResult]) {
+    let mut accepted_tests = vec![];
+
+    for run_result in run_results {
+        if let Ok(test_result) = &run_result.result {
+            if !test_result.is_success() {
+                match aureum::accept::accept_test_result(&run_result.test_case, test_result) {
+                    Ok(true) => accepted_tests.push((run_result.test_case.id(), Ok(()))),
+                    Ok(false) => {}
+                    Err(err) => accepted_tests.push((run_result.test_case.id(), Err(err))),
+                }
+            }
+        }
+    }
+
+    if !accepted_tests.is_empty() {
+        report::print_accepted_tests(&accepted_tests);
+    }
+}
+
+fn convert_files(paths: &[PathBuf]) {
+    let source_files = file::locate_convertible_files(paths);
+
+    if source_files.is_empty() {
+        report::print_no_convertible_files();
+        exit(INVALID_USER_INPUT_EXIT_CODE);
+    }
+
+    let conversions = source_files
+        .into_iter()
+        .map(|(path, format)| {
+            let result = convert::convert(&path, format).and_then(|conversion| {
+                convert::write_conversion(&conversion)?;
+                Ok(conversion)
+            });
+            (path, result)
+        })
+        .collect::<Vec<_>>();
+
+    report::print_conversions(&conversions);
+
+    if conversions.iter().any(|(_, result)| result.is_err()) {
         exit(TEST_FAILURE_EXIT_CODE)
     }
 }
 
+// Tests are run sequentially by default
+fn get_jobs(args: &Args, project_config: &ProjectConfig) -> NonZeroUsize {
+    if let Some(jobs) = args.jobs {
+        jobs
+    } else if args.run_tests_in_parallel {
+        thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+    } else {
+        project_config.jobs.unwrap_or(NonZeroUsize::MIN)
+    }
+}
+
+// A random seed is picked when using `--shuffle` without `--seed`
+fn get_seed(args: &Args) -> Option<u64> {
+    if let Some(seed) = args.seed {
+        Some(seed)
+    } else if args.shuffle {
+        Some(fastrand::u64(..))
+    } else {
+        None
+    }
+}
+
 fn get_report_format(args: &Args) -> ReportFormat {
     match args.output_format {
         OutputFormat::Summary => ReportFormat::Summary {
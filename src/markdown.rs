@@ -0,0 +1,306 @@
+//! Extract golden tests from ```` ```console ```` (or `~~~console`) blocks in Markdown files
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A fenced block with the info string `console`, e.g.
+///
+/// ````text
+/// ```console exit_code=1
+/// $ my-program --flag
+/// Expected output
+/// ```
+/// ````
+pub struct ConsoleBlock {
+    pub name: String, // Derived from the closest heading, or the line number (Unique within the file)
+    pub line: usize,  // 1-based line number of the opening fence
+    pub attributes: BTreeMap<String, String>,
+    pub commands: Vec<ConsoleCommand>,
+}
+
+pub struct ConsoleCommand {
+    pub line: String, // Without the `$ ` prefix
+    pub expected_output: String,
+}
+
+pub fn parse_console_blocks(content: &str) -> Vec<ConsoleBlock> {
+    let mut blocks = vec![];
+    let mut heading: Option<String> = None;
+    let mut names = BTreeSet::new();
+
+    let mut lines = content.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        if let Some(title) = parse_heading(line) {
+            heading = Some(slugify(title));
+            continue;
+        }
+
+        let Some((indent, fence, info_string)) = parse_opening_fence(line) else {
+            continue;
+        };
+
+        let block_lines = lines
+            .by_ref()
+            .map(|(_, line)| remove_indent(line, indent))
+            .take_while(|line| !is_closing_fence(line, fence))
+            .collect::<Vec<_>>();
+
+        let mut info = info_string.split_whitespace();
+        if info.next() != Some("console") {
+            continue;
+        }
+
+        let attributes = info
+            .filter_map(|x| x.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+
+        // Blocks without a heading (or with an empty one) are named after their line number
+        let line_number = i + 1;
+        let base_name = match heading.as_deref() {
+            Some(heading) if !heading.is_empty() => heading.to_owned(),
+            _ => format!("line_{}", line_number),
+        };
+
+        // A suffix is added to names that are already taken, e.g. by a heading
+        // with the same text, or by a heading like "Usage 2" after two
+        // blocks under "Usage"
+        let mut name = base_name.clone();
+        let mut count = 1;
+        while !names.insert(name.clone()) {
+            count += 1;
+            name = format!("{}_{}", base_name, count);
+        }
+
+        blocks.push(ConsoleBlock {
+            name,
+            line: line_number,
+            attributes,
+            commands: parse_commands(&block_lines),
+        });
+    }
+
+    blocks
+}
+
+// The maximum indentation of headings and fences, since lines indented by
+// four spaces are code blocks (See CommonMark)
+const MAX_INDENT: usize = 3;
+
+// Returns the title of an ATX heading, e.g. `## Usage`
+fn parse_heading(line: &str) -> Option<&str> {
+    let (_, line) = split_indent(line)?;
+    let level = line.chars().take_while(|x| *x == '#').count();
+    let title = &line[level..];
+
+    if !(1..=6).contains(&level) || !(title.is_empty() || title.starts_with([' ', '\t'])) {
+        return None;
+    }
+
+    // The closing sequence (e.g. `## Usage ##`) is not part of the title
+    let title = title.trim();
+    let without_closing_sequence = title.trim_end_matches('#');
+    if without_closing_sequence.is_empty() || without_closing_sequence.ends_with([' ', '\t']) {
+        Some(without_closing_sequence)
+    } else {
+        Some(title)
+    }
+}
+
+// Returns the indentation and the fence (e.g. ```` ``` ```` or `~~~`)
+// together with the info string
+fn parse_opening_fence(line: &str) -> Option<(usize, &str, &str)> {
+    let (indent, line) = split_indent(line)?;
+    let fence_char = line.chars().next().filter(|x| matches!(x, '`' | '~'))?;
+    let fence_length = line.chars().take_while(|x| *x == fence_char).count();
+    if fence_length < 3 {
+        return None;
+    }
+
+    let (fence, info_string) = line.split_at(fence_length);
+    Some((indent, fence, info_string))
+}
+
+// The closing fence must use the same character and be at least as long as
+// the opening fence
+fn is_closing_fence(line: &str, fence: &str) -> bool {
+    let Some((_, line)) = split_indent(line) else {
+        return false;
+    };
+    let line = line.trim_end();
+
+    line.starts_with(fence) && line.chars().all(|x| fence.starts_with(x))
+}
+
+fn split_indent(line: &str) -> Option<(usize, &str)> {
+    let indent = line.chars().take_while(|x| *x == ' ').count();
+    (indent <= MAX_INDENT).then(|| (indent, &line[indent..]))
+}
+
+// The content of an indented block is indented by the same amount
+fn remove_indent(line: &str, indent: usize) -> &str {
+    let spaces = line.chars().take(indent).take_while(|x| *x == ' ').count();
+    &line[spaces..]
+}
+
+// The output of a command consists of all lines until the next command
+fn parse_commands(lines: &[&str]) -> Vec<ConsoleCommand> {
+    let mut commands: Vec<ConsoleCommand> = vec![];
+
+    for line in lines {
+        if let Some(command_line) = line.strip_prefix("$ ") {
+            commands.push(ConsoleCommand {
+                line: command_line.to_owned(),
+                expected_output: String::new(),
+            });
+        } else if let Some(command) = commands.last_mut() {
+            command.expected_output.push_str(line);
+            command.expected_output.push('\n');
+        }
+    }
+
+    commands
+}
+
+fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+
+    for c in title.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.ends_with('_') {
+            slug.push('_');
+        }
+    }
+
+    slug.trim_matches('_').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse_console_blocks() {
+        let content = indoc! {"
+            # Usage
+
+            ```console
+            $ echo Hello
+            Hello
+            $ true
+            ```
+
+            ```console exit_code=1
+            $ false
+            ```
+
+            ```bash
+            $ echo Ignored
+            ```
+
+            ## Other heading!
+            ```console
+            $ echo
+
+            ```
+        "};
+
+        let blocks = parse_console_blocks(content);
+        let names = blocks.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["usage", "usage_2", "other_heading"]);
+        assert_eq!(blocks[0].line, 3);
+        assert_eq!(blocks[0].commands.len(), 2);
+        assert_eq!(blocks[0].commands[0].line, "echo Hello");
+        assert_eq!(blocks[0].commands[0].expected_output, "Hello\n");
+        assert_eq!(blocks[0].commands[1].expected_output, "");
+        assert_eq!(blocks[1].attributes.get("exit_code").unwrap(), "1");
+        assert_eq!(blocks[2].commands[0].expected_output, "\n");
+    }
+
+    #[test]
+    fn test_parse_console_blocks_with_tilde_fences() {
+        let content = indoc! {"
+            ~~~~console exit_code=1
+            $ echo Hello
+            ```
+            ~~~
+            ~~~~
+        "};
+
+        let blocks = parse_console_blocks(content);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].attributes.get("exit_code").unwrap(), "1");
+        assert_eq!(blocks[0].commands[0].expected_output, "```\n~~~\n");
+    }
+
+    #[test]
+    fn test_parse_console_blocks_without_heading() {
+        let content = indoc! {"
+            Text
+
+            ```console
+            $ echo
+            ```
+        "};
+
+        let blocks = parse_console_blocks(content);
+
+        assert_eq!(blocks[0].name, "line_3");
+    }
+
+    #[test]
+    fn test_parse_console_blocks_with_colliding_names() {
+        let content = indoc! {"
+            # Usage
+            ```console
+            $ echo
+            ```
+            ```console
+            $ echo
+            ```
+
+            # Usage 2
+            ```console
+            $ echo
+            ```
+
+            # Usage
+            ```console
+            $ echo
+            ```
+        "};
+
+        let blocks = parse_console_blocks(content);
+        let names = blocks.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["usage", "usage_2", "usage_2_2", "usage_3"]);
+    }
+
+    #[test]
+    fn test_parse_headings_and_indented_fences() {
+        let content = indoc! {"
+            # Usage
+            #tag
+            #!/bin/sh
+
+              ```console
+              $ echo Hello
+              Hello
+              ```
+
+                ```console
+                $ echo Ignored
+                ```
+        "};
+
+        let blocks = parse_console_blocks(content);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].name, "usage");
+        assert_eq!(blocks[0].commands[0].line, "echo Hello");
+        assert_eq!(blocks[0].commands[0].expected_output, "Hello\n");
+    }
+}
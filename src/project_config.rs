@@ -0,0 +1,33 @@
+//! Settings that apply to all tests, read from `aureum.toml` in the current directory
+
+use crate::toml_config::{self, ConfigError};
+use crate::utils::source::SourceLocation;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::Path;
+
+pub const FILE_NAME: &str = "aureum.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub jobs: Option<NonZeroUsize>, // Overridden by `--jobs`
+}
+
+/// Returns `None` if the file does not exist
+pub fn read_project_config(dir: &Path) -> Result<Option<ProjectConfig>, ConfigError> {
+    let content = match fs::read_to_string(dir.join(FILE_NAME)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(ConfigError::FailedToReadFile(err)),
+    };
+
+    toml::from_str(&content).map(Some).map_err(|err| {
+        let location = err
+            .line_col()
+            .and_then(|(line, column)| SourceLocation::from_line_col(&content, line, column));
+        toml_config::format_error(err.to_string(), location)
+    })
+}
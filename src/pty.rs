@@ -0,0 +1,301 @@
+//! Run a program attached to a pseudo-terminal (Unix only)
+
+use crate::cancellation::Cancellation;
+use crate::test_case::{Interaction, RunError, TestStep};
+use crate::test_result::InteractionFailure;
+use crate::utils::process::{self, CpuTime};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long the program may keep running after the end of the interaction
+const EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The end-of-file character (Ctrl-D) of the terminal
+const EOF: &[u8] = b"\x04";
+
+pub enum PtyOutput {
+    Finished { transcript: String, exit_code: i32 },
+    InteractionFailed(InteractionFailure),
+}
+
+pub fn run(
+    step: &TestStep,
+    current_dir: &Path,
+    cancellation: &Cancellation,
+) -> Result<(PtyOutput, Option<CpuTime>), RunError> {
+    let (master, slave) = open_pty().map_err(RunError::IOError)?;
+
+    let mut cmd = Command::new(&step.program);
+    cmd.current_dir(current_dir);
+    cmd.args(&step.arguments);
+    cmd.stdin(Stdio::from(slave.try_clone().map_err(RunError::IOError)?));
+    cmd.stdout(Stdio::from(slave.try_clone().map_err(RunError::IOError)?));
+    cmd.stderr(Stdio::from(slave));
+
+    // Make the pseudo-terminal the controlling terminal of the program
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let child = process::spawn(&mut cmd).map_err(RunError::IOError)?;
+
+    // The parent must not keep the slave side open, otherwise reading from the
+    // master side never reaches the end
+    drop(cmd);
+
+    // The program is killed if the test run is cancelled while it is running
+    let process_id = child.id();
+    let child_id = cancellation.register(child);
+    let interaction_result = interact(step, &master, process_id);
+    let (exit_status, cpu_time) =
+        process::wait(cancellation.unregister(child_id)).map_err(RunError::IOError)?;
+
+    // Closing the master side hangs up the terminal, which sends `SIGHUP` to
+    // the program if it is still exiting
+    drop(master);
+
+    if cancellation.is_cancelled() {
+        return Err(RunError::Killed);
+    }
+
+    let pty_output = match interaction_result? {
+        InteractionOutput::Finished { transcript } => PtyOutput::Finished {
+            transcript: decode_utf8(transcript)?,
+            exit_code: exit_status.code().ok_or(RunError::MissingExitCode)?,
+        },
+        InteractionOutput::TimedOut {
+            expected,
+            timeout,
+            transcript,
+        } => PtyOutput::InteractionFailed(InteractionFailure::TimedOut {
+            expected,
+            timeout,
+            transcript: decode_utf8(transcript)?,
+        }),
+        InteractionOutput::Exited {
+            expected,
+            transcript,
+        } => PtyOutput::InteractionFailed(InteractionFailure::Exited {
+            expected,
+            exit_code: exit_status.code(),
+            transcript: decode_utf8(transcript)?,
+        }),
+        InteractionOutput::DidNotExit {
+            timeout,
+            transcript,
+        } => PtyOutput::InteractionFailed(InteractionFailure::DidNotExit {
+            timeout,
+            transcript: decode_utf8(transcript)?,
+        }),
+    };
+
+    Ok((pty_output, cpu_time))
+}
+
+enum InteractionOutput {
+    Finished {
+        transcript: Vec<u8>,
+    },
+    TimedOut {
+        expected: String,
+        timeout: Duration,
+        transcript: Vec<u8>,
+    },
+    Exited {
+        expected: String,
+        transcript: Vec<u8>,
+    },
+    DidNotExit {
+        timeout: Duration,
+        transcript: Vec<u8>,
+    },
+}
+
+fn interact(
+    step: &TestStep,
+    master: &File,
+    process_id: u32,
+) -> Result<InteractionOutput, RunError> {
+    let mut writer = master.try_clone().map_err(RunError::IOError)?;
+    let output = read_in_background(master.try_clone().map_err(RunError::IOError)?);
+    let mut transcript = Vec::new();
+
+    if let Some(stdin) = &step.stdin {
+        writer
+            .write_all(stdin.as_bytes())
+            .map_err(RunError::IOError)?;
+    }
+
+    // Matches are searched for after the end of the previous match
+    let mut search_start = 0;
+    for interaction in &step.interaction {
+        match interaction {
+            Interaction::Send(text) => {
+                writer
+                    .write_all(text.as_bytes())
+                    .map_err(RunError::IOError)?;
+            }
+            Interaction::Expect { text, timeout } => {
+                let deadline = Instant::now() + *timeout;
+
+                loop {
+                    if let Some(position) = find(&transcript[search_start..], text.as_bytes()) {
+                        search_start += position + text.len();
+                        break;
+                    }
+
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    match output.recv_timeout(remaining) {
+                        Ok(chunk) => transcript.extend(chunk),
+                        Err(RecvTimeoutError::Timeout) => {
+                            kill_process_group(process_id);
+                            return Ok(InteractionOutput::TimedOut {
+                                expected: text.clone(),
+                                timeout: *timeout,
+                                transcript,
+                            });
+                        }
+                        // The terminal is closed when the program exits
+                        Err(RecvTimeoutError::Disconnected) => {
+                            kill_process_group(process_id);
+                            return Ok(InteractionOutput::Exited {
+                                expected: text.clone(),
+                                transcript,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Signal the end of the input, for programs that read until the end. The
+    // terminal stays open until the program has exited, so closing the writer
+    // is not enough. Writing fails if the program has already closed it.
+    let _ = writer.write_all(EOF);
+
+    // Read the remaining output until the program closes the terminal
+    let deadline = Instant::now() + EXIT_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match output.recv_timeout(remaining) {
+            Ok(chunk) => transcript.extend(chunk),
+            Err(RecvTimeoutError::Timeout) => {
+                kill_process_group(process_id);
+                return Ok(InteractionOutput::DidNotExit {
+                    timeout: EXIT_TIMEOUT,
+                    transcript,
+                });
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    Ok(InteractionOutput::Finished { transcript })
+}
+
+// The program is the leader of a new process group, which also includes any
+// processes it started
+fn kill_process_group(process_id: u32) {
+    unsafe {
+        libc::kill(-(process_id as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+fn open_pty() -> io::Result<(File, File)> {
+    // Other programs started in parallel must not inherit the terminal, otherwise
+    // it stays open until they exit
+    let (master, slave) = process::without_spawning(|| {
+        let (master, slave) = openpty()?;
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok((master, slave))
+    })?;
+
+    // Keep newlines as-is in the transcript, instead of converting them to `\r\n`
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        termios.c_oflag &= !libc::ONLCR;
+        if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok((master, slave))
+}
+
+fn openpty() -> io::Result<(File, File)> {
+    let mut master: RawFd = -1;
+    let mut slave: RawFd = -1;
+
+    let window_size = libc::winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    unsafe {
+        if libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &window_size as *const _ as *mut _,
+        ) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((File::from_raw_fd(master), File::from_raw_fd(slave)))
+    }
+}
+
+fn read_in_background(mut master: File) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+
+        // Reading fails with `EIO` on Linux when the program has closed the terminal
+        while let Ok(n) = master.read(&mut buf) {
+            if n == 0 || sender.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn decode_utf8(buf: Vec<u8>) -> Result<String, RunError> {
+    String::from_utf8(buf).map_err(|_| RunError::FailedToDecodeUtf8)
+}
@@ -1,22 +1,111 @@
+use crate::cancellation::Cancellation;
+use crate::compare::CompareMode;
+#[cfg(unix)]
+use crate::pty::{self, PtyOutput};
+use crate::test_hooks::{self, HookCommand, HookFailure, OnceSetup};
 use crate::test_id::TestId;
-use crate::test_result::{TestResult, ValueComparison};
-use crate::utils::file;
-use relative_path::RelativePathBuf;
+use crate::test_result::{
+    DirComparison, Divergence, FailedAssertion, ProgramOutput, StepResult, TestResult,
+    ValueComparison,
+};
+use crate::utils::{file, process};
+use relative_path::{RelativePath, RelativePathBuf};
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStderr, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Clone)]
 pub struct TestCase {
     pub source_file: RelativePathBuf,
     pub id: TestId,
+    pub description: Option<String>,
+    pub steps: Vec<TestStep>,
+    pub is_session: bool, // Configured using `steps`
+    pub sandbox: bool,    // Run inside an empty temporary directory
+    pub once_setup: Option<Arc<OnceSetup>>,
+    pub setup: Vec<HookCommand>,
+    pub teardown: Vec<HookCommand>,
+    pub expected_files: BTreeMap<String, Option<String>>, // `None` means that the file should not exist
+    pub expected_dir: Option<RelativePathBuf>,
+    pub retries: Option<u32>, // Overrides the default number of retries given to the test runner
+    pub exclusive: bool,      // Do not run any other tests at the same time
+    pub lock: Option<String>, // Tests with the same lock are not run at the same time
+    pub check_deterministic: bool, // Run the test twice and compare the output of the runs
+}
+
+/// A single run of a program. Sessions consist of multiple steps, while other
+/// tests consist of exactly one step.
+#[derive(Clone)]
+pub struct TestStep {
     pub description: Option<String>,
     pub program: PathBuf, // Expects an absolute path
     pub arguments: Vec<String>,
     pub stdin: Option<String>,
+    pub pty: bool, // Run the program attached to a pseudo-terminal
+    pub interaction: Vec<Interaction>,
     pub expected_stdout: Option<String>,
     pub expected_stderr: Option<String>,
+    pub expected_stdout_file: Option<RelativePathBuf>, // Set if `expected_stdout` is read from a file
+    pub expected_stderr_file: Option<RelativePathBuf>, // Set if `expected_stderr` is read from a file
     pub expected_exit_code: Option<i32>,
+    pub assertions: Vec<Assertion>,
+    pub max_duration: Option<Duration>, // The program fails if it runs for longer
+    pub stdout_compare: CompareMode,    // Used for `expected_stdout`
+    pub stderr_compare: CompareMode,    // Used for `expected_stderr`
+    pub stdout_filter: Option<HookCommand>, // Applied before the output is checked
+    pub stderr_filter: Option<HookCommand>,
+}
+
+/// Scripted input for a program running in a pseudo-terminal
+#[derive(Clone)]
+pub enum Interaction {
+    Expect { text: String, timeout: Duration },
+    Send(String),
+}
+
+/// Checks a part of the output, as an alternative to the complete expected output
+#[derive(Clone)]
+pub enum Assertion {
+    StdoutContains(String),
+    StdoutNotContains(String),
+    StderrContains(String),
+    StderrNotContains(String),
+}
+
+impl Assertion {
+    fn check(&self, stdout: &str, stderr: &str) -> Option<FailedAssertion> {
+        let (output, is_success) = match self {
+            Self::StdoutContains(text) => (stdout, stdout.contains(text)),
+            Self::StdoutNotContains(text) => (stdout, !stdout.contains(text)),
+            Self::StderrContains(text) => (stderr, stderr.contains(text)),
+            Self::StderrNotContains(text) => (stderr, !stderr.contains(text)),
+        };
+
+        if is_success {
+            None
+        } else {
+            Some(FailedAssertion {
+                assertion: self.clone(),
+                output: output.to_owned(),
+            })
+        }
+    }
+}
+
+impl TestStep {
+    pub fn name(&self, index: usize) -> String {
+        let step_number = index + 1;
+
+        if let Some(description) = &self.description {
+            format!("Step {} - {}", step_number, description)
+        } else {
+            format!("Step {}", step_number)
+        }
+    }
 }
 
 impl TestCase {
@@ -26,7 +115,7 @@ impl TestCase {
         if self.id.is_root() {
             file_path
         } else {
-            format!("{}:{}", file_path, self.id.to_string())
+            format!("{}:{}", file_path, self.id)
         }
     }
 }
@@ -35,53 +124,333 @@ pub enum RunError {
     FailedToDecodeUtf8,
     MissingExitCode,
     IOError(io::Error),
+    SetupFailed(HookFailure),
+    TeardownFailed(HookFailure),
+    FilterFailed(HookFailure),
+    PtyNotSupported,
+    Cancelled, // The test run was stopped before the test started
+    Killed,    // The test run was stopped while the test was running
+}
+
+pub fn run(test_case: &TestCase, cancellation: &Cancellation) -> Result<TestResult, RunError> {
+    if let Some(once_setup) = &test_case.once_setup {
+        once_setup
+            .run()
+            .map_err(|err| err.into_run_error(RunError::SetupFailed))?;
+    }
+
+    let sandbox = if test_case.sandbox {
+        Some(tempfile::tempdir().map_err(RunError::IOError)?)
+    } else {
+        None
+    };
+
+    let current_dir = match &sandbox {
+        Some(sandbox) => sandbox.path().to_path_buf(),
+        None => file::parent_dir(&test_case.source_file).to_logical_path("."),
+    };
+
+    // Teardown is also run if a setup command failed, since the commands
+    // before it may have changed something. Errors from the setup and the
+    // program take precedence.
+    let result = test_hooks::run_hooks(&test_case.setup, &current_dir)
+        .map_err(|err| err.into_run_error(RunError::SetupFailed))
+        .and_then(|()| run_program(test_case, &current_dir, cancellation));
+
+    let teardown_result = test_hooks::run_hooks(&test_case.teardown, &current_dir);
+    let test_result = result?;
+    teardown_result.map_err(|err| err.into_run_error(RunError::TeardownFailed))?;
+
+    Ok(test_result)
+}
+
+/// Compare a repeated run of a test with `check_deterministic` against the
+/// first run. The output is compared using the compare mode of each step.
+pub fn find_divergence(
+    test_case: &TestCase,
+    first_result: &TestResult,
+    test_result: &TestResult,
+    run: u32,
+) -> Option<Divergence> {
+    let step_results = first_result.steps.iter().zip(&test_result.steps);
+    for (i, (step, step_results)) in test_case.steps.iter().zip(step_results).enumerate() {
+        let (Some(Some(first_output)), Some(Some(output))) = (
+            step_results.0.as_ref().map(|x| &x.output),
+            step_results.1.as_ref().map(|x| &x.output),
+        ) else {
+            continue;
+        };
+
+        let stdout = compare_output(
+            &step.stdout_compare,
+            &Some(first_output.stdout.clone()),
+            output.stdout.clone(),
+        );
+        let stderr = compare_output(
+            &step.stderr_compare,
+            &Some(first_output.stderr.clone()),
+            output.stderr.clone(),
+        );
+        let exit_code = compare_result(&Some(first_output.exit_code), output.exit_code);
+
+        if !stdout.is_success() || !stderr.is_success() || !exit_code.is_success() {
+            return Some(Divergence {
+                run,
+                step: i,
+                stdout,
+                stderr,
+                exit_code,
+            });
+        }
+    }
+
+    None
+}
+
+fn run_program(
+    test_case: &TestCase,
+    current_dir: &Path,
+    cancellation: &Cancellation,
+) -> Result<TestResult, RunError> {
+    // The remaining steps are skipped after the first failing step
+    let mut steps = vec![];
+    let mut any_failed_steps = false;
+    for step in &test_case.steps {
+        if any_failed_steps {
+            steps.push(None);
+        } else {
+            let step_result = run_step(step, current_dir, cancellation)?;
+            any_failed_steps = !step_result.is_success();
+            steps.push(Some(step_result));
+        }
+    }
+
+    let mut files = BTreeMap::new();
+    for (path, expected_content) in &test_case.expected_files {
+        let content = read_optional_file(&current_dir.join(path))?
+            .map(String::from_utf8)
+            .transpose()
+            .map_err(|_| RunError::FailedToDecodeUtf8)?;
+        files.insert(
+            path.clone(),
+            compare_result(&Some(expected_content.clone()), content),
+        );
+    }
+
+    let dir = match &test_case.expected_dir {
+        Some(expected_dir) => Some(compare_dir(expected_dir, current_dir)?),
+        None => None,
+    };
+
+    Ok(TestResult {
+        steps,
+        files,
+        dir,
+        divergence: None,
+    })
 }
 
-pub fn run(test_case: &TestCase) -> Result<TestResult, RunError> {
-    let current_dir = file::parent_dir(&test_case.source_file).to_logical_path(".");
+fn run_step(
+    step: &TestStep,
+    current_dir: &Path,
+    cancellation: &Cancellation,
+) -> Result<StepResult, RunError> {
+    if step.pty {
+        return run_step_in_pty(step, current_dir, cancellation);
+    }
 
-    let mut cmd = Command::new(&test_case.program);
+    let mut cmd = Command::new(&step.program);
     cmd.current_dir(current_dir);
-    cmd.args(&test_case.arguments);
+    cmd.args(&step.arguments);
     cmd.stdin(Stdio::piped());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
 
-    let mut child = cmd.spawn().map_err(RunError::IOError)?;
+    // The processes started by the program must also be killed if the test
+    // run is cancelled, since they may keep the output open
+    process::new_process_group(&mut cmd);
+
+    let start_time = Instant::now();
+    let mut child = process::spawn(&mut cmd).map_err(RunError::IOError)?;
 
-    if let Some(stdin_string) = &test_case.stdin {
-        let mut stdin = child
+    // Stdin is only closed before the program exits if it is specified
+    let stdin = step.stdin.as_ref().map(|stdin_string| {
+        let stdin = child
             .stdin
             .take()
             .expect("Stdin should be configured to pipe");
+        (stdin, stdin_string.as_str())
+    });
+    let stdout = child
+        .stdout
+        .take()
+        .expect("Stdout should be configured to pipe");
+    let stderr = child
+        .stderr
+        .take()
+        .expect("Stderr should be configured to pipe");
+
+    // The program is killed if the test run is cancelled while it is running
+    let child_id = cancellation.register(child);
+    let output = communicate(stdin, stdout, stderr);
+    let (exit_status, cpu_time) =
+        process::wait(cancellation.unregister(child_id)).map_err(RunError::IOError)?;
+    let duration = start_time.elapsed();
+
+    if cancellation.is_cancelled() {
+        return Err(RunError::Killed);
+    }
+
+    let (stdout, stderr) = output?;
+    let exit_code = exit_status.code().ok_or(RunError::MissingExitCode)?;
+
+    let (stdout, unfiltered_stdout) = apply_filter(&step.stdout_filter, stdout, current_dir)?;
+    let (stderr, unfiltered_stderr) = apply_filter(&step.stderr_filter, stderr, current_dir)?;
+
+    Ok(StepResult {
+        failed_assertions: check_assertions(step, &stdout, &stderr),
+        stdout: compare_output(&step.stdout_compare, &step.expected_stdout, stdout.clone()),
+        stderr: compare_output(&step.stderr_compare, &step.expected_stderr, stderr.clone()),
+        unfiltered_stdout,
+        unfiltered_stderr,
+        exit_code: compare_result(&step.expected_exit_code, exit_code),
+        interaction_failure: None,
+        duration,
+        cpu_time,
+        exceeded_max_duration: check_max_duration(step, duration),
+        output: Some(ProgramOutput {
+            stdout,
+            stderr,
+            exit_code,
+        }),
+    })
+}
+
+fn communicate(
+    stdin: Option<(ChildStdin, &str)>,
+    mut stdout: ChildStdout,
+    mut stderr: ChildStderr,
+) -> Result<(String, String), RunError> {
+    if let Some((mut stdin, stdin_string)) = stdin {
         stdin
             .write_all(stdin_string.as_bytes())
             .map_err(RunError::IOError)?;
     }
 
-    let stdout = read_pipe_to_string(
-        &mut child
-            .stdout
-            .take()
-            .expect("Stdout should be configured to pipe"),
-    )?;
-    let stderr = read_pipe_to_string(
-        &mut child
-            .stderr
-            .take()
-            .expect("Stderr should be configured to pipe"),
-    )?;
+    let stdout = read_pipe_to_string(&mut stdout)?;
+    let stderr = read_pipe_to_string(&mut stderr)?;
+    Ok((stdout, stderr))
+}
 
-    let exit_status = child.wait().map_err(RunError::IOError)?;
-    let exit_code = exit_status
-        .code()
-        .map_or(Err(RunError::MissingExitCode), Ok)?;
+// The terminal transcript is compared against `expected_stdout`, since the
+// program writes both standard output and standard error to the terminal
+#[cfg(unix)]
+fn run_step_in_pty(
+    step: &TestStep,
+    current_dir: &Path,
+    cancellation: &Cancellation,
+) -> Result<StepResult, RunError> {
+    let start_time = Instant::now();
+    let (pty_output, cpu_time) = pty::run(step, current_dir, cancellation)?;
+    let duration = start_time.elapsed();
 
-    Ok(TestResult {
-        stdout: compare_result(&test_case.expected_stdout, stdout),
-        stderr: compare_result(&test_case.expected_stderr, stderr),
-        exit_code: compare_result(&test_case.expected_exit_code, exit_code),
-    })
+    match pty_output {
+        PtyOutput::Finished {
+            transcript,
+            exit_code,
+        } => {
+            let (transcript, unfiltered_stdout) =
+                apply_filter(&step.stdout_filter, transcript, current_dir)?;
+
+            Ok(StepResult {
+                failed_assertions: check_assertions(step, &transcript, ""),
+                stdout: compare_output(
+                    &step.stdout_compare,
+                    &step.expected_stdout,
+                    transcript.clone(),
+                ),
+                stderr: ValueComparison::NotChecked,
+                unfiltered_stdout,
+                unfiltered_stderr: None,
+                exit_code: compare_result(&step.expected_exit_code, exit_code),
+                interaction_failure: None,
+                duration,
+                cpu_time,
+                exceeded_max_duration: check_max_duration(step, duration),
+                output: Some(ProgramOutput {
+                    stdout: transcript,
+                    stderr: String::new(),
+                    exit_code,
+                }),
+            })
+        }
+        PtyOutput::InteractionFailed(interaction_failure) => Ok(StepResult {
+            stdout: ValueComparison::NotChecked,
+            stderr: ValueComparison::NotChecked,
+            unfiltered_stdout: None,
+            unfiltered_stderr: None,
+            exit_code: ValueComparison::NotChecked,
+            failed_assertions: vec![],
+            interaction_failure: Some(interaction_failure),
+            duration,
+            cpu_time,
+            exceeded_max_duration: None,
+            output: None,
+        }),
+    }
+}
+
+#[cfg(not(unix))]
+fn run_step_in_pty(
+    _step: &TestStep,
+    _current_dir: &Path,
+    _cancellation: &Cancellation,
+) -> Result<StepResult, RunError> {
+    Err(RunError::PtyNotSupported)
+}
+
+// Returns the filtered output together with the original output, if a filter is configured
+fn apply_filter(
+    filter: &Option<HookCommand>,
+    output: String,
+    current_dir: &Path,
+) -> Result<(String, Option<String>), RunError> {
+    match filter {
+        Some(filter) => {
+            let filtered_output = filter
+                .filter(&output, current_dir)
+                .map_err(|err| err.into_run_error(RunError::FilterFailed))?;
+            Ok((filtered_output, Some(output)))
+        }
+        None => Ok((output, None)),
+    }
+}
+
+fn check_max_duration(step: &TestStep, duration: Duration) -> Option<Duration> {
+    step.max_duration
+        .filter(|max_duration| duration > *max_duration)
+}
+
+fn check_assertions(step: &TestStep, stdout: &str, stderr: &str) -> Vec<FailedAssertion> {
+    step.assertions
+        .iter()
+        .filter_map(|x| x.check(stdout, stderr))
+        .collect()
+}
+
+fn compare_output(
+    compare: &CompareMode,
+    expected: &Option<String>,
+    got: String,
+) -> ValueComparison<String> {
+    match expected {
+        Some(expected) if compare.is_match(expected, &got) => ValueComparison::Matches(got),
+        Some(expected) => ValueComparison::Diff {
+            expected: expected.clone(),
+            got,
+        },
+        None => ValueComparison::NotChecked,
+    }
 }
 
 fn compare_result<T: PartialEq + Clone>(expected: &Option<T>, got: T) -> ValueComparison<T> {
@@ -99,6 +468,32 @@ fn compare_result<T: PartialEq + Clone>(expected: &Option<T>, got: T) -> ValueCo
     }
 }
 
+fn compare_dir(expected_dir: &RelativePath, got_dir: &Path) -> Result<DirComparison, RunError> {
+    let expected_path = expected_dir.to_logical_path(".");
+
+    // A missing directory is treated as empty, to allow it to be created by `--accept`
+    let mut paths = if expected_path.is_dir() {
+        file::list_files(&expected_path).map_err(RunError::IOError)?
+    } else {
+        vec![]
+    };
+    paths.extend(file::list_files(got_dir).map_err(RunError::IOError)?);
+    paths.sort();
+    paths.dedup();
+
+    let mut files = BTreeMap::new();
+    for path in paths {
+        let expected = read_optional_file(&path.to_logical_path(&expected_path))?;
+        let got = read_optional_file(&path.to_logical_path(got_dir))?;
+        files.insert(path.to_string(), compare_result(&Some(expected), got));
+    }
+
+    Ok(DirComparison {
+        path: expected_dir.to_string(),
+        files,
+    })
+}
+
 fn read_pipe_to_string<T>(pipe: &mut T) -> Result<String, RunError>
 where
     T: Read,
@@ -107,3 +502,12 @@ where
     pipe.read_to_end(&mut buf).map_err(RunError::IOError)?;
     String::from_utf8(buf).map_or(Err(RunError::FailedToDecodeUtf8), Ok)
 }
+
+// Not decoded, since expected directories may contain binary files
+fn read_optional_file(path: &Path) -> Result<Option<Vec<u8>>, RunError> {
+    match fs::read(path) {
+        Ok(buf) => Ok(Some(buf)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(RunError::IOError(err)),
+    }
+}
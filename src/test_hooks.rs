@@ -0,0 +1,180 @@
+use crate::test_case::RunError;
+use crate::utils::process;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+
+/// A command that is run before or after a test (e.g. `setup` and `teardown`),
+/// or that filters the output of a program (e.g. `stdout_filter`)
+#[derive(Clone)]
+pub struct HookCommand {
+    pub program_name: String, // The program as written in the config file
+    pub program: PathBuf,     // Expects an absolute path
+    pub arguments: Vec<String>,
+}
+
+impl HookCommand {
+    pub fn run(&self, current_dir: &Path) -> Result<(), HookError> {
+        let failed_to_start = |err: std::io::Error| {
+            HookError::Failed(HookFailure::FailedToStart {
+                command: self.to_string(),
+                error: err.to_string(),
+            })
+        };
+
+        let output = process::spawn(
+            Command::new(&self.program)
+                .current_dir(current_dir)
+                .args(&self.arguments)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .and_then(|child| child.wait_with_output())
+        .map_err(failed_to_start)?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(self.unsuccessful_exit(output))
+        }
+    }
+
+    /// Run the command with `input` as standard input, and return its standard output
+    pub fn filter(&self, input: &str, current_dir: &Path) -> Result<String, HookError> {
+        let failed_to_start = |err: std::io::Error| {
+            HookError::Failed(HookFailure::FailedToStart {
+                command: self.to_string(),
+                error: err.to_string(),
+            })
+        };
+
+        let mut child = process::spawn(
+            Command::new(&self.program)
+                .current_dir(current_dir)
+                .args(&self.arguments)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .map_err(failed_to_start)?;
+
+        // Write in the background, since the command may start writing before
+        // it has read all of its input
+        let mut stdin = child
+            .stdin
+            .take()
+            .expect("Stdin should be configured to pipe");
+        let input = input.to_owned();
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+        let output = child.wait_with_output().map_err(failed_to_start)?;
+        let _ = writer.join(); // The command is allowed to ignore its input
+
+        if output.status.success() {
+            decode_utf8(output.stdout)
+        } else {
+            Err(self.unsuccessful_exit(output))
+        }
+    }
+
+    fn unsuccessful_exit(&self, output: Output) -> HookError {
+        let stdout = match decode_utf8(output.stdout) {
+            Ok(stdout) => stdout,
+            Err(err) => return err,
+        };
+        let stderr = match decode_utf8(output.stderr) {
+            Ok(stderr) => stderr,
+            Err(err) => return err,
+        };
+
+        HookError::Failed(HookFailure::UnsuccessfulExit {
+            command: self.to_string(),
+            exit_code: output.status.code(),
+            stdout,
+            stderr,
+        })
+    }
+}
+
+fn decode_utf8(bytes: Vec<u8>) -> Result<String, HookError> {
+    String::from_utf8(bytes).map_err(|_| HookError::FailedToDecodeUtf8)
+}
+
+impl fmt::Display for HookCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![self.program_name.clone()];
+        parts.extend(self.arguments.iter().cloned());
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+#[derive(Clone)]
+pub enum HookFailure {
+    FailedToStart {
+        command: String,
+        error: String,
+    },
+    UnsuccessfulExit {
+        command: String,
+        exit_code: Option<i32>,
+        stdout: String,
+        stderr: String,
+    },
+}
+
+/// The output of a hook command is decoded the same way as the output of the
+/// program, so a command with invalid UTF-8 output fails the test as well
+#[derive(Clone)]
+pub enum HookError {
+    Failed(HookFailure),
+    FailedToDecodeUtf8,
+}
+
+impl HookError {
+    /// Convert to the error of a test, e.g. `RunError::SetupFailed`
+    pub fn into_run_error(self, failed: fn(HookFailure) -> RunError) -> RunError {
+        match self {
+            HookError::Failed(failure) => failed(failure),
+            HookError::FailedToDecodeUtf8 => RunError::FailedToDecodeUtf8,
+        }
+    }
+}
+
+/// Run the commands in order, stopping at the first failure
+pub fn run_hooks(commands: &[HookCommand], current_dir: &Path) -> Result<(), HookError> {
+    for command in commands {
+        command.run(current_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Setup commands that are shared by all tests in a config file
+///
+/// The commands are run the first time `run` is called, and the result is
+/// reused for subsequent calls (also when tests are run in parallel).
+pub struct OnceSetup {
+    commands: Vec<HookCommand>,
+    current_dir: PathBuf,
+    result: OnceLock<Result<(), HookError>>,
+}
+
+impl OnceSetup {
+    pub fn new(commands: Vec<HookCommand>, current_dir: PathBuf) -> OnceSetup {
+        OnceSetup {
+            commands,
+            current_dir,
+            result: OnceLock::new(),
+        }
+    }
+
+    pub fn run(&self) -> Result<(), HookError> {
+        self.result
+            .get_or_init(|| run_hooks(&self.commands, &self.current_dir))
+            .clone()
+    }
+}
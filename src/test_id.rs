@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct TestId {
     id_path: Vec<String>,
@@ -39,17 +41,18 @@ impl TestId {
     }
 
     pub fn to_prefixed_string(&self) -> String {
-        format!(":{}", self.to_string())
+        format!(":{}", self)
     }
 }
 
-impl ToString for TestId {
-    fn to_string(&self) -> String {
-        self.id_path.join(".")
+impl fmt::Display for TestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id_path.join("."))
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
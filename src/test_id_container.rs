@@ -47,6 +47,7 @@ impl TestIdContainer {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -1,12 +1,112 @@
+use crate::test_case::Assertion;
+use crate::utils::process::CpuTime;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
 pub struct TestResult {
+    pub steps: Vec<Option<StepResult>>, // `None` means that the step was not run
+    pub files: BTreeMap<String, ValueComparison<Option<String>>>, // `None` means that the file does not exist
+    pub dir: Option<DirComparison>,
+    pub divergence: Option<Divergence>, // Set if the output differed from the first run
+}
+
+impl TestResult {
+    pub fn is_success(&self) -> bool {
+        self.steps
+            .iter()
+            .all(|x| x.as_ref().is_some_and(|x| x.is_success()))
+            && self.files.values().all(|x| x.is_success())
+            && self.dir.as_ref().is_none_or(|x| x.is_success())
+            && self.divergence.is_none()
+    }
+
+    /// The total time spent running the programs of the test
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().flatten().map(|x| x.duration).sum()
+    }
+
+    /// The total CPU time of the programs of the test, if it was measured
+    pub fn cpu_time(&self) -> Option<CpuTime> {
+        self.steps.iter().flatten().map(|x| x.cpu_time).sum()
+    }
+}
+
+pub struct StepResult {
     pub stdout: ValueComparison<String>,
     pub stderr: ValueComparison<String>,
+    pub unfiltered_stdout: Option<String>, // The output before `stdout_filter` was applied
+    pub unfiltered_stderr: Option<String>,
     pub exit_code: ValueComparison<i32>,
+    pub failed_assertions: Vec<FailedAssertion>,
+    pub interaction_failure: Option<InteractionFailure>,
+    pub duration: Duration, // Wall-clock time from starting the program until it exited
+    pub cpu_time: Option<CpuTime>, // Only measured on Unix
+    pub exceeded_max_duration: Option<Duration>, // Set to `max_duration` if the program ran for longer
+    pub output: Option<ProgramOutput>,           // `None` if the program did not finish
 }
 
-impl TestResult {
+impl StepResult {
+    pub fn is_success(&self) -> bool {
+        self.stdout.is_success()
+            && self.stderr.is_success()
+            && self.exit_code.is_success()
+            && self.failed_assertions.is_empty()
+            && self.interaction_failure.is_none()
+            && self.exceeded_max_duration.is_none()
+    }
+}
+
+/// The output of a program after it is filtered, used to compare repeated runs
+pub struct ProgramOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+/// A repeated run of a test with `check_deterministic` produced different
+/// output than the first run
+pub struct Divergence {
+    pub run: u32,                        // Counting from 1
+    pub step: usize,                     // Index of the first step that differed
+    pub stdout: ValueComparison<String>, // The output of the first run is expected
+    pub stderr: ValueComparison<String>,
+    pub exit_code: ValueComparison<i32>,
+}
+
+pub struct FailedAssertion {
+    pub assertion: Assertion,
+    pub output: String, // The complete output that was checked
+}
+
+/// The program in a pseudo-terminal did not print the expected text. The
+/// transcript contains everything the program printed before it was stopped.
+pub enum InteractionFailure {
+    TimedOut {
+        expected: String,
+        timeout: Duration,
+        transcript: String,
+    },
+    /// The program exited (or closed the terminal) before the text appeared
+    Exited {
+        expected: String,
+        exit_code: Option<i32>, // `None` if the program was terminated by a signal
+        transcript: String,
+    },
+    /// The program was still running after the interaction ended
+    DidNotExit {
+        timeout: Duration,
+        transcript: String,
+    },
+}
+
+pub struct DirComparison {
+    pub path: String,
+    pub files: BTreeMap<String, ValueComparison<Option<Vec<u8>>>>, // `None` means that the file does not exist
+}
+
+impl DirComparison {
     pub fn is_success(&self) -> bool {
-        self.stdout.is_success() && self.stderr.is_success() && self.exit_code.is_success()
+        self.files.values().all(|x| x.is_success())
     }
 }
 
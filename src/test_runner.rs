@@ -1,12 +1,29 @@
+use crate::cancellation::Cancellation;
 use crate::formats::tree::{Leaf, Node};
 use crate::formats::{tap, tree};
 use crate::test_case::{self, RunError, TestCase};
 use crate::test_result::TestResult;
+use crate::utils::duration;
 use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
 
 pub struct ReportConfig {
     pub number_of_tests: usize,
     pub format: ReportFormat,
+    pub verbose: bool, // Show the output of programs before it is filtered
+    pub order: ReportOrder,
+    pub report_slowest: Option<NonZeroUsize>, // Number of slowest tests to list after the test run
+    pub seed: Option<u64>,                    // The seed used to shuffle the tests, if any
+}
+
+/// The order in which results are reported when tests are run in parallel
+pub enum ReportOrder {
+    Declaration, // Results are held back until all preceding tests have been reported
+    Completion,
 }
 
 pub enum ReportFormat {
@@ -14,17 +31,59 @@ pub enum ReportFormat {
     Tap,
 }
 
+pub struct RunConfig {
+    pub jobs: NonZeroUsize, // Number of tests to run at the same time
+    pub retries: u32,       // Used by tests that do not specify `retries`
+    pub repeat: NonZeroU32, // Number of times each test is run
+    pub max_failures: Option<NonZeroUsize>, // Stop the test run after this many failed tests
+}
+
 pub struct RunResult {
     pub test_case: TestCase,
-    pub result: Result<TestResult, RunError>,
+    pub result: Result<TestResult, RunError>, // Result of the last attempt
+    pub attempts: u32,
+    pub runs: u32, // Number of times the test was run in the last attempt (See `--repeat`)
 }
 
 impl RunResult {
     pub fn is_success(&self) -> bool {
-        match &self.result {
-            Ok(test_result) => test_result.is_success(),
-            Err(_) => false,
-        }
+        is_success(&self.result)
+    }
+
+    /// The test failed at first, but passed on a retry
+    pub fn is_flaky(&self) -> bool {
+        self.is_success() && self.attempts > 1
+    }
+
+    /// The test run was stopped before the test started
+    pub fn is_not_run(&self) -> bool {
+        matches!(self.result, Err(RunError::Cancelled))
+    }
+
+    /// The test run was stopped while the test was running
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.result, Err(RunError::Killed))
+    }
+
+    pub fn is_failure(&self) -> bool {
+        !self.is_success() && !self.is_not_run() && !self.is_stopped()
+    }
+
+    /// The output of a repeated run differed from the first run (See `check_deterministic`)
+    pub fn is_nondeterministic(&self) -> bool {
+        self.result.as_ref().is_ok_and(|x| x.divergence.is_some())
+    }
+
+    /// Tests that failed to run are not timed
+    pub fn duration(&self) -> Option<Duration> {
+        self.result.as_ref().ok().map(|x| x.duration())
+    }
+}
+
+fn is_success(result: &Result<TestResult, RunError>) -> bool {
+    match result {
+        Ok(test_result) => test_result.is_success(),
+        Err(_) => false,
     }
 }
 
@@ -32,67 +91,213 @@ impl RunResult {
 
 pub fn run_test_cases(
     report_config: &ReportConfig,
+    run_config: &RunConfig,
     test_cases: &[TestCase],
-    run_in_parallel: bool,
 ) -> Vec<RunResult> {
-    let run = |(i, test_case)| -> Vec<RunResult> {
-        let result = test_case::run(test_case);
-
-        report_test_case(report_config, i, test_case, &result);
+    let cancellation = Cancellation::new();
+    let slots = Mutex::new(ResultSlots {
+        results: test_cases.iter().map(|_| None).collect(),
+        number_of_reported_results: 0,
+        number_of_failures: 0,
+    });
+
+    let locks = Locks {
+        exclusive: RwLock::new(()),
+        named: test_cases
+            .iter()
+            .filter_map(|x| x.lock.clone())
+            .map(|name| (name, Mutex::new(())))
+            .collect(),
+    };
 
-        vec![RunResult {
-            test_case: test_case.clone(),
-            result,
-        }]
+    let run = |(i, test_case)| {
+        let run_result = run_with_locks(&locks, run_config, &cancellation, test_case);
+
+        let mut slots = slots.lock().expect("Lock should not be poisoned");
+        if run_result.is_failure() {
+            slots.number_of_failures += 1;
+            if run_config
+                .max_failures
+                .is_some_and(|x| slots.number_of_failures >= x.get())
+            {
+                cancellation.cancel();
+            }
+        }
+        match report_config.order {
+            ReportOrder::Declaration => {
+                slots.results[i] = Some(run_result);
+                while let Some(Some(run_result)) =
+                    slots.results.get(slots.number_of_reported_results)
+                {
+                    report_test_case(report_config, slots.number_of_reported_results, run_result);
+                    slots.number_of_reported_results += 1;
+                }
+            }
+            ReportOrder::Completion => {
+                report_test_case(report_config, i, &run_result);
+                slots.results[i] = Some(run_result);
+            }
+        }
     };
 
     report_start(report_config);
 
-    let run_results = if run_in_parallel {
-        test_cases
-            .par_iter()
-            .enumerate()
-            .map(run)
-            .reduce(Vec::new, |x, y| itertools::concat([x, y]))
+    if run_config.jobs.get() > 1 {
+        // A dedicated pool is used, since the global pool is sized to the number of CPUs
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(run_config.jobs.get())
+            .build()
+            .expect("Thread pool should be created");
+
+        pool.install(|| test_cases.par_iter().enumerate().for_each(run));
     } else {
-        test_cases
-            .iter()
-            .enumerate()
-            .map(run)
-            .fold(vec![], |x, y| itertools::concat([x, y]))
-    };
+        test_cases.iter().enumerate().for_each(run);
+    }
+
+    let run_results = slots
+        .into_inner()
+        .expect("Lock should not be poisoned")
+        .results
+        .into_iter()
+        .map(|x| x.expect("All tests should have been run"))
+        .collect::<Vec<_>>();
 
     report_summary(report_config, &run_results);
 
     run_results
 }
 
+// Results are stored in declaration order, regardless of when the tests finish
+struct ResultSlots {
+    results: Vec<Option<RunResult>>,
+    number_of_reported_results: usize,
+    number_of_failures: usize,
+}
+
+// Every test holds the `exclusive` lock for reading, except exclusive tests
+// which need it for writing. The named lock is always acquired last.
+struct Locks {
+    exclusive: RwLock<()>,
+    named: BTreeMap<String, Mutex<()>>,
+}
+
+fn run_with_locks(
+    locks: &Locks,
+    run_config: &RunConfig,
+    cancellation: &Cancellation,
+    test_case: &TestCase,
+) -> RunResult {
+    let _exclusive_guard;
+    let _shared_guard;
+    if test_case.exclusive {
+        _exclusive_guard = locks
+            .exclusive
+            .write()
+            .expect("Lock should not be poisoned");
+    } else {
+        _shared_guard = locks.exclusive.read().expect("Lock should not be poisoned");
+    }
+
+    let _named_guard = test_case.lock.as_ref().map(|name| {
+        locks.named[name]
+            .lock()
+            .expect("Lock should not be poisoned")
+    });
+
+    run_with_retries(run_config, cancellation, test_case)
+}
+
+// Tests that have not started when the test run is cancelled are not run at all
+fn run_with_retries(
+    run_config: &RunConfig,
+    cancellation: &Cancellation,
+    test_case: &TestCase,
+) -> RunResult {
+    let retries = test_case.retries.unwrap_or(run_config.retries);
+
+    let mut result = Err(RunError::Cancelled);
+    let mut attempts = 0;
+    let mut runs = 0;
+    while attempts <= retries && !cancellation.is_cancelled() {
+        (result, runs) = run_repeatedly(run_config, cancellation, test_case);
+        attempts += 1;
+
+        if is_success(&result) {
+            break;
+        }
+    }
+
+    RunResult {
+        test_case: test_case.clone(),
+        result,
+        attempts,
+        runs,
+    }
+}
+
+// The runs stop at the first failure, which is returned together with the
+// number of runs. Tests with `check_deterministic` are run at least twice.
+fn run_repeatedly(
+    run_config: &RunConfig,
+    cancellation: &Cancellation,
+    test_case: &TestCase,
+) -> (Result<TestResult, RunError>, u32) {
+    let number_of_runs = if test_case.check_deterministic {
+        run_config.repeat.get().max(2)
+    } else {
+        run_config.repeat.get()
+    };
+
+    let mut first_result = None;
+    let mut run = 1;
+    loop {
+        let mut result = test_case::run(test_case, cancellation);
+
+        if let (Some(first_result), Ok(test_result)) = (&first_result, &mut result) {
+            if test_case.check_deterministic && test_result.is_success() {
+                test_result.divergence =
+                    test_case::find_divergence(test_case, first_result, test_result, run);
+            }
+        }
+
+        if run == number_of_runs || !is_success(&result) {
+            return (result, run);
+        }
+
+        if first_result.is_none() {
+            first_result = result.ok();
+        }
+        run += 1;
+    }
+}
+
 // REPORTING
 
 fn report_start(report_config: &ReportConfig) {
     match report_config.format {
         ReportFormat::Summary { show_all_tests: _ } => {
-            summary_print_start(report_config.number_of_tests);
+            summary_print_start(report_config.number_of_tests, report_config.seed);
         }
         ReportFormat::Tap => {
-            tap_print_start(report_config.number_of_tests);
+            tap_print_start(report_config.number_of_tests, report_config.seed);
         }
     }
 }
 
-fn report_test_case(
-    report_config: &ReportConfig,
-    index: usize,
-    test_case: &TestCase,
-    result: &Result<TestResult, RunError>,
-) {
+fn report_test_case(report_config: &ReportConfig, index: usize, run_result: &RunResult) {
     match report_config.format {
         ReportFormat::Summary { show_all_tests: _ } => {
-            summary_print_test_case(result);
+            summary_print_test_case(run_result);
         }
         ReportFormat::Tap => {
             let test_number_indent_level = report_config.number_of_tests.to_string().len();
-            tap_print_test_case(index + 1, test_case, result, test_number_indent_level);
+            tap_print_test_case(
+                index + 1,
+                run_result,
+                test_number_indent_level,
+                report_config.verbose,
+                report_config.verbose || report_config.report_slowest.is_some(),
+            );
         }
     }
 }
@@ -100,54 +305,95 @@ fn report_test_case(
 fn report_summary(report_config: &ReportConfig, run_results: &[RunResult]) {
     match report_config.format {
         ReportFormat::Summary { show_all_tests } => {
-            summary_print_summary(report_config.number_of_tests, show_all_tests, run_results);
+            summary_print_summary(
+                report_config.number_of_tests,
+                show_all_tests,
+                report_config.verbose,
+                report_config.report_slowest,
+                run_results,
+            );
         }
         ReportFormat::Tap => {
-            tap_print_summary();
+            tap_print_summary(report_config.report_slowest, run_results);
         }
     }
 }
 
 // SUMMARY HELPERS
 
-fn summary_print_start(number_of_tests: usize) {
-    println!("🚀 Running {} tests:", number_of_tests)
+fn summary_print_start(number_of_tests: usize, seed: Option<u64>) {
+    match seed {
+        Some(seed) => println!(
+            "🚀 Running {} tests (Shuffled using --seed {}):",
+            number_of_tests, seed
+        ),
+        None => println!("🚀 Running {} tests:", number_of_tests),
+    }
 }
 
-fn summary_print_test_case(result: &Result<TestResult, RunError>) {
-    match result {
-        Ok(test_result) => {
-            if test_result.is_success() {
-                print!(".")
-            } else {
-                print!("F")
-            }
-        }
-        Err(_) => {
-            print!("F")
-        }
+fn summary_print_test_case(run_result: &RunResult) {
+    if run_result.is_not_run() || run_result.is_stopped() {
+        // Nothing is printed for tests that did not finish before the test run was stopped
+    } else if run_result.is_flaky() {
+        print!("f")
+    } else if run_result.is_success() {
+        print!(".")
+    } else {
+        print!("F")
     }
 }
 
-fn summary_print_summary(number_of_tests: usize, show_all_tests: bool, run_results: &[RunResult]) {
+fn summary_print_summary(
+    number_of_tests: usize,
+    show_all_tests: bool,
+    verbose: bool,
+    report_slowest: Option<NonZeroUsize>,
+    run_results: &[RunResult],
+) {
     println!(); // Add newline to dots
 
     let mut is_any_test_cases_printed = false;
 
     for run_result in run_results {
-        let test_failed = !run_result.is_success();
-        if show_all_tests || test_failed {
+        // Flaky tests are always shown, so that they do not pass unnoticed
+        if show_all_tests || run_result.is_failure() || run_result.is_flaky() {
             if !is_any_test_cases_printed {
                 println!();
                 is_any_test_cases_printed = true;
             }
 
-            summary_print_result(run_result);
+            summary_print_result(run_result, verbose);
         }
     }
 
-    let number_of_passed_tests = run_results.iter().filter(|t| t.is_success()).count();
-    let number_of_failed_tests = number_of_tests - number_of_passed_tests;
+    if let Some(count) = report_slowest {
+        let nodes = slowest_tests(run_results, count)
+            .into_iter()
+            .map(|(duration, test_id)| {
+                Leaf(vec![format!(
+                    "{} {}",
+                    duration::format_duration(duration),
+                    test_id
+                )])
+            })
+            .collect();
+
+        let tree = Node(format!("🐢 Slowest {} tests", count), nodes);
+        let content =
+            tree::draw_tree(&tree).unwrap_or_else(|_| String::from("Failed to draw tree\n"));
+        println!();
+        print!("{}", content); // Already contains newline
+    }
+
+    let number_of_successful_tests = run_results.iter().filter(|t| t.is_success()).count();
+    let number_of_flaky_tests = run_results.iter().filter(|t| t.is_flaky()).count();
+    let number_of_passed_tests = number_of_successful_tests - number_of_flaky_tests;
+    let number_of_not_run_tests = run_results.iter().filter(|t| t.is_not_run()).count();
+    let number_of_stopped_tests = run_results.iter().filter(|t| t.is_stopped()).count();
+    let number_of_failed_tests = number_of_tests
+        - number_of_successful_tests
+        - number_of_not_run_tests
+        - number_of_stopped_tests;
 
     let status = if number_of_failed_tests == 0 {
         "OK"
@@ -155,14 +401,23 @@ fn summary_print_summary(number_of_tests: usize, show_all_tests: bool, run_resul
         "FAIL"
     };
 
+    let mut counts = vec![format!("{} passed", number_of_passed_tests)];
+    if number_of_flaky_tests > 0 {
+        counts.push(format!("{} flaky", number_of_flaky_tests));
+    }
+    counts.push(format!("{} failed", number_of_failed_tests));
+    if number_of_stopped_tests > 0 {
+        counts.push(format!("{} stopped", number_of_stopped_tests));
+    }
+    if number_of_not_run_tests > 0 {
+        counts.push(format!("{} not run", number_of_not_run_tests));
+    }
+
     println!();
-    println!(
-        "Test result: {} ({} passed, {} failed)",
-        status, number_of_passed_tests, number_of_failed_tests,
-    );
+    println!("Test result: {} ({})", status, counts.join(", "));
 }
 
-fn summary_print_result(run_result: &RunResult) {
+fn summary_print_result(run_result: &RunResult, verbose: bool) {
     let test_id = run_result.test_case.id();
 
     let message: String;
@@ -172,16 +427,35 @@ fn summary_print_result(run_result: &RunResult) {
         message = test_id;
     }
 
-    if run_result.is_success() {
+    if run_result.is_not_run() {
+        println!("➖ {} (Not run)", message)
+    } else if run_result.is_stopped() {
+        println!("➖ {} (Stopped while running)", message)
+    } else if run_result.is_flaky() {
+        let test_heading = format!("⚠️  {}", message);
+        let tree = Node(
+            test_heading,
+            vec![Leaf(vec![format!(
+                "Flaky (Passed after {} attempts)",
+                run_result.attempts
+            )])],
+        );
+        let content =
+            tree::draw_tree(&tree).unwrap_or_else(|_| String::from("Failed to draw tree\n"));
+        print!("{}", content); // Already contains newline
+    } else if run_result.is_success() {
         println!("✅ {}", message)
     } else {
-        let nodes = match &run_result.result {
-            Ok(result) => tree::nodes_from_test_result(result),
-            Err(_) => {
-                vec![Leaf(vec![String::from("Failed to run test")])]
-            }
+        let mut nodes = match &run_result.result {
+            Ok(result) => tree::nodes_from_test_result(&run_result.test_case, result, verbose),
+            Err(run_error) => tree::nodes_from_run_error(run_error),
         };
 
+        // A divergence already shows which run differed
+        if run_result.runs > 1 && !run_result.is_nondeterministic() {
+            nodes.insert(0, Leaf(vec![format!("Failed on run {}", run_result.runs)]));
+        }
+
         let test_heading = format!("❌ {}", message);
         let tree = Node(test_heading, nodes);
         let content =
@@ -192,17 +466,23 @@ fn summary_print_result(run_result: &RunResult) {
 
 // TAP HELPERS
 
-fn tap_print_start(number_of_tests: usize) {
+fn tap_print_start(number_of_tests: usize, seed: Option<u64>) {
     tap::print_version();
     tap::print_plan(1, number_of_tests);
+    if let Some(seed) = seed {
+        tap::print_comment(&format!("Shuffled using --seed {}", seed));
+    }
 }
 
 fn tap_print_test_case(
     test_number: usize,
-    test_case: &TestCase,
-    result: &Result<TestResult, RunError>,
+    run_result: &RunResult,
     indent_level: usize,
+    verbose: bool,
+    show_timing: bool,
 ) {
+    let test_case = &run_result.test_case;
+
     let message: String;
     if let Some(description) = &test_case.description {
         message = format!("{} # {}", test_case.id(), description);
@@ -210,18 +490,90 @@ fn tap_print_test_case(
         message = test_case.id();
     }
 
-    match result {
+    match &run_result.result {
         Ok(test_result) => {
-            if test_result.is_success() {
-                tap::print_ok(test_number, &message, indent_level)
+            if test_case.is_session {
+                tap::print_subtest(&message, test_case, test_result, verbose);
+            }
+
+            let timing = Some(tap::Timing {
+                duration: test_result.duration(),
+                cpu_time: test_result.cpu_time(),
+            })
+            .filter(|_| show_timing);
+
+            if run_result.is_flaky() {
+                tap::print_ok_flaky(
+                    test_number,
+                    &message,
+                    run_result.attempts,
+                    timing.as_ref(),
+                    indent_level,
+                )
+            } else if test_result.is_success() {
+                match &timing {
+                    Some(timing) => {
+                        tap::print_ok_with_timing(test_number, &message, timing, indent_level)
+                    }
+                    None => tap::print_ok(test_number, &message, indent_level),
+                }
             } else {
-                tap::print_not_ok(test_number, &message, test_result, indent_level)
+                tap::print_not_ok(
+                    test_number,
+                    &message,
+                    test_case,
+                    test_result,
+                    indent_level,
+                    verbose,
+                    &tap::RunDetails {
+                        timing,
+                        run: Some(run_result.runs)
+                            .filter(|x| *x > 1 && !run_result.is_nondeterministic()),
+                    },
+                )
             }
         }
-        Err(_) => {
-            tap::print_not_ok_diagnostics(test_number, &message, "Failed to run test", indent_level)
+        // Tests that never started are left out, since `Bail out!` follows the
+        // last test that was run
+        Err(RunError::Cancelled) => {}
+        Err(run_error) => {
+            tap::print_not_ok_run_error(test_number, &message, run_error, indent_level)
         }
     }
 }
 
-fn tap_print_summary() {}
+// The remaining tests are not reported, so `Bail out!` marks the point where the
+// test run was stopped
+fn tap_print_summary(report_slowest: Option<NonZeroUsize>, run_results: &[RunResult]) {
+    if run_results.iter().any(|x| x.is_not_run() || x.is_stopped()) {
+        let number_of_failed_tests = run_results.iter().filter(|x| x.is_failure()).count();
+        tap::print_bail_out(&format!(
+            "Stopped after {} failed tests",
+            number_of_failed_tests
+        ));
+    }
+
+    if let Some(count) = report_slowest {
+        tap::print_comment(&format!("Slowest {} tests", count));
+        for (duration, test_id) in slowest_tests(run_results, count) {
+            tap::print_comment(&format!(
+                "  {} {}",
+                duration::format_duration(duration),
+                test_id
+            ));
+        }
+    }
+}
+
+// SHARED HELPERS
+
+// Tests with the same duration are listed in declaration order
+fn slowest_tests(run_results: &[RunResult], count: NonZeroUsize) -> Vec<(Duration, String)> {
+    let mut durations = run_results
+        .iter()
+        .filter_map(|x| Some((x.duration()?, x.test_case.id())))
+        .collect::<Vec<_>>();
+    durations.sort_by_key(|x| Reverse(x.0));
+    durations.truncate(count.get());
+    durations
+}
@@ -1,26 +1,56 @@
-use crate::test_case::TestCase;
+use crate::compare::CompareMode;
+use crate::markdown;
+use crate::test_case::{Assertion, Interaction, TestCase, TestStep};
+use crate::test_hooks::{HookCommand, OnceSetup};
 use crate::test_id::TestId;
-use crate::utils::file;
-use relative_path::{RelativePath, RelativePathBuf};
+use crate::utils::source::SourceLocation;
+use crate::utils::{duration, file, string};
+use relative_path::{Component, RelativePath, RelativePathBuf};
+use serde::de::IgnoredAny;
 use serde::Deserialize;
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use serde_yaml::{Mapping, Value as YamlValue};
 use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::fs;
 use std::io;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use toml_edit::{ImDocument, Item, Key, Table, Value};
 
 // READ CONFIG FILE
 
-pub struct ParsedTomlConfig {
+pub struct ParsedConfig {
     pub data: TomlConfigData,
     pub tests: BTreeMap<TestId, TestDetails>,
+    pub once_details: OnceDetails,
+}
+
+/// Errors in the `[once]` table apply to the whole file (No tests should be
+/// run if there are any)
+pub struct OnceDetails {
+    pub validation_errors: BTreeSet<TestCaseValidationError>,
+    pub error_locations: BTreeMap<TestCaseValidationError, SourceLocation>,
 }
 
 pub struct TestDetails {
+    pub definition: TestDefinition,
     pub requirements: BTreeSet<Requirement>,
-    pub program_path: ProgramPath,
+    pub program_paths: Vec<ProgramPath>, // One for each step
     pub test_case: Result<TestCase, BTreeSet<TestCaseValidationError>>,
+    pub error_locations: BTreeMap<TestCaseValidationError, SourceLocation>,
+}
+
+/// The fields that define a test (including inherited fields and `[once]`),
+/// which can be compared to find the tests that changed when a config file is
+/// edited
+#[derive(Clone, PartialEq)]
+pub struct TestDefinition {
+    once_config: TomlOnceConfig,
+    config: TomlConfig,
 }
 
 pub enum ProgramPath {
@@ -47,7 +77,7 @@ impl ProgramPath {
     }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum TestCaseValidationError {
     MissingExternalFile(String),
     MissingEnvVar(String),
@@ -55,24 +85,110 @@ pub enum TestCaseValidationError {
     ProgramRequired,
     ProgramNotFound(String),
     ExpectationRequired,
+    InvalidExpectedFilePath(String),
+    ExpectedFileContentRequired(String),
+    HookCommandRequired,
+    StepRequired,
+    InvalidDuration(String),
+    InteractionRequiresPty,
+    InvalidCompareMode(String),
+    InvalidJsonPointer(String),
+    InvalidNumericTolerance(String),
+    UnknownField {
+        field: String,
+        suggestion: Option<String>,
+    },
 }
 
-pub enum TomlConfigError {
+pub enum ConfigError {
     FailedToReadFile(io::Error),
-    FailedToParseTomlConfig(toml::de::Error),
+    FailedToParseConfig {
+        message: String,
+        location: Option<SourceLocation>,
+    },
+}
+
+/// Config files are written in TOML, but YAML and JSON are supported as well.
+/// Tests can also be embedded in Markdown files.
+#[derive(PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+    Markdown,
+}
+
+impl ConfigFormat {
+    pub const FILE_EXTENSIONS: [&'static str; 4] = ["au.toml", "au.yaml", "au.yml", "au.json"];
+    pub const MARKDOWN_FILE_EXTENSION: &'static str = "md"; // Only searched for when requested
+
+    pub fn from_path(path: &RelativePath) -> ConfigFormat {
+        match path.extension() {
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            Some("json") => ConfigFormat::Json,
+            Some("md") => ConfigFormat::Markdown,
+            _ => ConfigFormat::Toml,
+        }
+    }
 }
 
-pub fn parse_toml_config(source_file: &RelativePath) -> Result<ParsedTomlConfig, TomlConfigError> {
+pub fn parse_config(source_file: &RelativePath) -> Result<ParsedConfig, ConfigError> {
     let source_path = source_file.to_logical_path(".");
 
-    let toml_content =
-        fs::read_to_string(source_path).map_err(TomlConfigError::FailedToReadFile)?;
-    let toml_config = toml::from_str::<TomlConfig>(&toml_content)
-        .map_err(TomlConfigError::FailedToParseTomlConfig)?;
+    let toml_content = fs::read_to_string(source_path).map_err(ConfigError::FailedToReadFile)?;
 
-    let toml_configs = split_toml_config(toml_config);
+    let toml_config_file = match ConfigFormat::from_path(source_file) {
+        ConfigFormat::Toml => toml::from_str::<TomlConfigFile>(&toml_content)
+            .map_err(|err| parse_error(&toml_content, err))?,
+        ConfigFormat::Yaml => {
+            serde_yaml::from_str::<TomlConfigFile>(&toml_content).map_err(|err| {
+                let location = err.location().and_then(|x| {
+                    SourceLocation::from_span(&toml_content, x.index()..x.index() + 1)
+                });
+                yaml_or_json_error(&toml_content, err.to_string(), location)
+            })?
+        }
+        ConfigFormat::Json => {
+            serde_json::from_str::<TomlConfigFile>(&toml_content).map_err(|err| {
+                // Both line and column are 1-based
+                let location = SourceLocation::from_line_col(
+                    &toml_content,
+                    err.line().saturating_sub(1),
+                    err.column().saturating_sub(1),
+                );
+                yaml_or_json_error(&toml_content, err.to_string(), location)
+            })?
+        }
+        ConfigFormat::Markdown => {
+            let config = markdown_to_config(&toml_content)?;
+            serde_yaml::from_value::<TomlConfigFile>(YamlValue::Mapping(config.clone())).map_err(
+                |err| {
+                    let invalid_field = serde_json::to_value(&config)
+                        .ok()
+                        .and_then(|root| find_invalid_field(&root));
+                    match invalid_field {
+                        Some(field_path) => format_error(
+                            format!("{} for key `{}`", err, field_path.join(".")),
+                            None,
+                        ),
+                        None => format_error(err.to_string(), None),
+                    }
+                },
+            )?
+        }
+    };
+
+    // Only used to locate validation errors in the source (TOML only)
+    let document = match ConfigFormat::from_path(source_file) {
+        ConfigFormat::Toml => ImDocument::parse(toml_content.as_str()).ok(),
+        ConfigFormat::Yaml | ConfigFormat::Json | ConfigFormat::Markdown => None,
+    };
+
+    let once_config = toml_config_file.once.unwrap_or_default();
+    let toml_configs = split_toml_config(toml_config_file.config);
 
     let mut requirements = BTreeSet::new();
+    add_hook_requirements(&mut requirements, &once_config.setup);
     for toml_config in toml_configs.values() {
         requirements.extend(get_requirements_from_leaf_config(toml_config));
     }
@@ -80,39 +196,328 @@ pub fn parse_toml_config(source_file: &RelativePath) -> Result<ParsedTomlConfig,
     let source_dir = file::parent_dir(source_file).to_logical_path(".");
     let data = gather_requirements(&requirements, &source_dir);
 
+    // Setup commands in `[once]` are shared by all tests in the file
+    let mut once_validation_errors = BTreeSet::new();
+    check_unknown_fields(
+        &mut once_validation_errors,
+        &once_config.unknown_fields,
+        ONCE_CONFIG_FIELDS,
+        "once.",
+    );
+    let once_setup_commands = read_hook_commands(
+        &mut once_validation_errors,
+        once_config.setup.clone(),
+        &source_dir,
+        &data,
+    );
+    let once_setup = if once_setup_commands.is_empty() {
+        None
+    } else {
+        Some(Arc::new(OnceSetup::new(
+            once_setup_commands,
+            source_dir.clone(),
+        )))
+    };
+
     let mut tests = BTreeMap::new();
 
     for (test_id, toml_config) in toml_configs {
-        let test_details =
-            build_test_details(toml_config, source_file.to_owned(), test_id.clone(), &data);
+        let definition = TestDefinition {
+            once_config: once_config.clone(),
+            config: toml_config.clone(),
+        };
+        let mut test_details = build_test_details(
+            toml_config,
+            definition,
+            source_file.to_owned(),
+            test_id.clone(),
+            &data,
+            once_setup.clone(),
+        );
+
+        if let (Some(document), Err(validation_errors)) = (&document, &test_details.test_case) {
+            test_details.error_locations =
+                locate_validation_errors(document, &test_id, validation_errors);
+        }
 
         tests.insert(test_id, test_details);
     }
 
-    Ok(ParsedTomlConfig { data, tests })
+    let once_error_locations = match &document {
+        Some(document) => {
+            locate_validation_errors(document, &TestId::root(), &once_validation_errors)
+        }
+        None => BTreeMap::new(),
+    };
+
+    Ok(ParsedConfig {
+        data,
+        tests,
+        once_details: OnceDetails {
+            validation_errors: once_validation_errors,
+            error_locations: once_error_locations,
+        },
+    })
 }
 
 // TOML STRUCTURE
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize)]
+struct TomlConfigFile {
+    once: Option<TomlOnceConfig>,
+    #[serde(flatten)]
+    config: TomlConfig,
+}
+
+#[derive(Deserialize, Default, Clone, PartialEq)]
+struct TomlOnceConfig {
+    setup: Option<Vec<Vec<ConfigValue<String>>>>,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, IgnoredAny>,
+}
+
+const ONCE_CONFIG_FIELDS: &[&str] = &["setup"];
+
+#[derive(Deserialize, Clone, PartialEq)]
 struct TomlConfig {
     description: Option<ConfigValue<String>>,
     program: Option<ConfigValue<String>>,
     program_arguments: Option<Vec<ConfigValue<String>>>,
     stdin: Option<ConfigValue<String>>,
-    expected_stdout: Option<ConfigValue<String>>,
-    expected_stderr: Option<ConfigValue<String>>,
+    pty: Option<bool>,
+    interaction: Option<Vec<TomlInteraction>>,
+    setup: Option<Vec<Vec<ConfigValue<String>>>>,
+    teardown: Option<Vec<Vec<ConfigValue<String>>>>,
+    expected_stdout: Option<TomlExpectedOutput>,
+    expected_stderr: Option<TomlExpectedOutput>,
     expected_exit_code: Option<ConfigValue<i32>>,
+    stdout_contains: Option<Vec<ConfigValue<String>>>,
+    stdout_not_contains: Option<Vec<ConfigValue<String>>>,
+    stderr_contains: Option<Vec<ConfigValue<String>>>,
+    stderr_not_contains: Option<Vec<ConfigValue<String>>>,
+    max_duration: Option<String>,
+    compare: Option<TomlCompare>,
+    stdout_compare: Option<TomlCompare>,
+    stderr_compare: Option<TomlCompare>,
+    stdout_filter: Option<Vec<ConfigValue<String>>>,
+    stderr_filter: Option<Vec<ConfigValue<String>>>,
+    expected_files: Option<BTreeMap<String, ExpectedFile>>,
+    expected_dir: Option<String>,
+    steps: Option<Vec<TomlStepConfig>>,
+    retries: Option<u32>,
+    exclusive: Option<bool>,
+    lock: Option<String>,
+    check_deterministic: Option<bool>,
     tests: Option<BTreeMap<String, TomlConfig>>,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, IgnoredAny>,
+}
+
+// Used to suggest alternatives to unknown fields
+const TOML_CONFIG_FIELDS: &[&str] = &[
+    "description",
+    "program",
+    "program_arguments",
+    "stdin",
+    "pty",
+    "interaction",
+    "setup",
+    "teardown",
+    "expected_stdout",
+    "expected_stderr",
+    "expected_exit_code",
+    "stdout_contains",
+    "stdout_not_contains",
+    "stderr_contains",
+    "stderr_not_contains",
+    "max_duration",
+    "compare",
+    "stdout_compare",
+    "stderr_compare",
+    "stdout_filter",
+    "stderr_filter",
+    "expected_files",
+    "expected_dir",
+    "steps",
+    "retries",
+    "exclusive",
+    "lock",
+    "check_deterministic",
+    "tests",
+];
+
+#[derive(Deserialize, Clone, PartialEq)]
+struct TomlStepConfig {
+    description: Option<ConfigValue<String>>,
+    program: Option<ConfigValue<String>>,
+    program_arguments: Option<Vec<ConfigValue<String>>>,
+    stdin: Option<ConfigValue<String>>,
+    pty: Option<bool>,
+    interaction: Option<Vec<TomlInteraction>>,
+    expected_stdout: Option<TomlExpectedOutput>,
+    expected_stderr: Option<TomlExpectedOutput>,
+    expected_exit_code: Option<ConfigValue<i32>>,
+    stdout_contains: Option<Vec<ConfigValue<String>>>,
+    stdout_not_contains: Option<Vec<ConfigValue<String>>>,
+    stderr_contains: Option<Vec<ConfigValue<String>>>,
+    stderr_not_contains: Option<Vec<ConfigValue<String>>>,
+    max_duration: Option<String>,
+    compare: Option<TomlCompare>,
+    stdout_compare: Option<TomlCompare>,
+    stderr_compare: Option<TomlCompare>,
+    stdout_filter: Option<Vec<ConfigValue<String>>>,
+    stderr_filter: Option<Vec<ConfigValue<String>>>,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, IgnoredAny>,
+}
+
+const TOML_STEP_CONFIG_FIELDS: &[&str] = &[
+    "description",
+    "program",
+    "program_arguments",
+    "stdin",
+    "pty",
+    "interaction",
+    "expected_stdout",
+    "expected_stderr",
+    "expected_exit_code",
+    "stdout_contains",
+    "stdout_not_contains",
+    "stderr_contains",
+    "stderr_not_contains",
+    "max_duration",
+    "compare",
+    "stdout_compare",
+    "stderr_compare",
+    "stdout_filter",
+    "stderr_filter",
+];
+
+impl TomlStepConfig {
+    fn has_expectations(&self) -> bool {
+        self.expected_stdout.is_some()
+            || self.expected_stderr.is_some()
+            || self.expected_exit_code.is_some()
+            || self.stdout_contains.is_some()
+            || self.stdout_not_contains.is_some()
+            || self.stderr_contains.is_some()
+            || self.stderr_not_contains.is_some()
+            || self.max_duration.is_some()
+            || self
+                .interaction
+                .iter()
+                .flatten()
+                .any(|x| matches!(x, TomlInteraction::Expect { .. }))
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum TomlInteraction {
+    Expect {
+        expect: ConfigValue<String>,
+        timeout: Option<String>,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+    Send {
+        send: ConfigValue<String>,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+}
+
+// E.g. `compare = "unordered-lines"` or `compare = { json = { ignore = ["/timestamp"] } }`
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum TomlCompare {
+    Mode(String),
+    Json {
+        json: TomlJsonCompare,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+    NumericTolerance {
+        numeric_tolerance: TomlNumericTolerance,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+}
+
+// E.g. `expected_stdout = { json = { file = "output.json" } }`, which also compares the output as JSON
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum TomlExpectedOutput {
+    Json {
+        json: ConfigValue<String>,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+    Text(ConfigValue<String>),
+}
+
+impl TomlExpectedOutput {
+    fn value(&self) -> &ConfigValue<String> {
+        match self {
+            Self::Json { json, .. } => json,
+            Self::Text(value) => value,
+        }
+    }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
+struct TomlJsonCompare {
+    ignore: Option<Vec<String>>,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, IgnoredAny>,
+}
+
+// E.g. `1e-6` (an absolute tolerance) or `{ absolute = 1e-9, relative = 1e-6 }`.
+// Omitted tolerances are zero.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum TomlNumericTolerance {
+    Absolute(f64),
+    Table {
+        absolute: Option<f64>,
+        relative: Option<f64>,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
 #[serde(untagged)]
 enum ConfigValue<T> {
     Literal(T),
-    WrappedLiteral { value: T },
-    ReadFromFile { file: String },
-    FetchFromEnv { env: String },
+    WrappedLiteral {
+        value: T,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+    ReadFromFile {
+        file: String,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+    FetchFromEnv {
+        env: String,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+}
+
+const CONFIG_VALUE_FIELDS: &[&str] = &["value", "file", "env"];
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum ExpectedFile {
+    Exists {
+        exists: bool,
+        #[serde(flatten)]
+        unknown_fields: BTreeMap<String, IgnoredAny>,
+    },
+    Content(ConfigValue<String>),
 }
 
 // REQUIREMENTS
@@ -127,16 +532,50 @@ fn get_requirements_from_leaf_config(config: &TomlConfig) -> BTreeSet<Requiremen
     let mut requirements = BTreeSet::new();
 
     add_requirement(&mut requirements, &config.description);
-    add_requirement(&mut requirements, &config.program);
-    add_requirement(&mut requirements, &config.stdin);
-    add_requirement(&mut requirements, &config.expected_stdout);
-    add_requirement(&mut requirements, &config.expected_stderr);
-    add_requirement(&mut requirements, &config.expected_exit_code);
 
-    if let Some(arguments) = &config.program_arguments {
-        for argument in arguments {
-            let requirement = get_requirement(argument);
-            requirements.extend(requirement)
+    for step_config in get_step_configs(config) {
+        add_requirement(&mut requirements, &step_config.description);
+        add_requirement(&mut requirements, &step_config.program);
+        add_requirement(&mut requirements, &step_config.stdin);
+        add_requirement(&mut requirements, &step_config.expected_exit_code);
+
+        for expected_output in [&step_config.expected_stdout, &step_config.expected_stderr] {
+            requirements.extend(
+                expected_output
+                    .as_ref()
+                    .and_then(|x| get_requirement(x.value())),
+            );
+        }
+
+        for interaction in step_config.interaction.iter().flatten() {
+            let value = match interaction {
+                TomlInteraction::Expect { expect, .. } => expect,
+                TomlInteraction::Send { send, .. } => send,
+            };
+            requirements.extend(get_requirement(value));
+        }
+
+        for values in [
+            &step_config.program_arguments,
+            &step_config.stdout_contains,
+            &step_config.stdout_not_contains,
+            &step_config.stderr_contains,
+            &step_config.stderr_not_contains,
+            &step_config.stdout_filter,
+            &step_config.stderr_filter,
+        ] {
+            requirements.extend(values.iter().flatten().filter_map(get_requirement));
+        }
+    }
+
+    add_hook_requirements(&mut requirements, &config.setup);
+    add_hook_requirements(&mut requirements, &config.teardown);
+
+    if let Some(expected_files) = &config.expected_files {
+        for expected_file in expected_files.values() {
+            if let ExpectedFile::Content(content) = expected_file {
+                requirements.extend(get_requirement(content))
+            }
         }
     }
 
@@ -149,14 +588,25 @@ fn add_requirement<T>(requirements: &mut BTreeSet<Requirement>, value: &Option<C
     requirements.extend(value.as_ref().and_then(get_requirement));
 }
 
+fn add_hook_requirements(
+    requirements: &mut BTreeSet<Requirement>,
+    commands: &Option<Vec<Vec<ConfigValue<String>>>>,
+) {
+    for command in commands.iter().flatten() {
+        requirements.extend(command.iter().filter_map(get_requirement));
+    }
+}
+
 fn get_requirement<T>(config_value: &ConfigValue<T>) -> Option<Requirement> {
     match config_value {
         ConfigValue::Literal(_) => None,
-        ConfigValue::WrappedLiteral { value: _ } => None,
-        ConfigValue::ReadFromFile { file: filename } => {
+        ConfigValue::WrappedLiteral { .. } => None,
+        ConfigValue::ReadFromFile { file: filename, .. } => {
             Some(Requirement::ExternalFile(filename.clone()))
         }
-        ConfigValue::FetchFromEnv { env: var_name } => Some(Requirement::EnvVar(var_name.clone())),
+        ConfigValue::FetchFromEnv { env: var_name, .. } => {
+            Some(Requirement::EnvVar(var_name.clone()))
+        }
     }
 }
 
@@ -216,9 +666,11 @@ fn read_from_env(var_name: &String) -> Result<String, env::VarError> {
 
 fn build_test_details(
     toml_config: TomlConfig,
+    definition: TestDefinition,
     source_file: RelativePathBuf,
     id: TestId,
     data: &TomlConfigData,
+    once_setup: Option<Arc<OnceSetup>>,
 ) -> TestDetails {
     let current_dir = file::parent_dir(&source_file);
     let mut validation_errors = BTreeSet::new();
@@ -226,18 +678,153 @@ fn build_test_details(
     // Requirements
     let requirements = get_requirements_from_leaf_config(&toml_config);
 
-    // Program path
-    let program = read_from_config_value(&mut validation_errors, toml_config.program, data);
-    let program_path = get_program_path(
-        program.unwrap_or_default(),
+    // Validate fields in config file
+
+    check_unknown_fields(
+        &mut validation_errors,
+        &toml_config.unknown_fields,
+        TOML_CONFIG_FIELDS,
+        "",
+    );
+
+    let step_configs = get_step_configs(&toml_config);
+    let is_session = toml_config.steps.is_some();
+
+    if step_configs.is_empty() {
+        validation_errors.insert(TestCaseValidationError::StepRequired);
+    }
+
+    if !step_configs.iter().any(|x| x.has_expectations())
+        && toml_config.expected_files.is_none()
+        && toml_config.expected_dir.is_none()
+        && toml_config.check_deterministic != Some(true)
+    {
+        validation_errors.insert(TestCaseValidationError::ExpectationRequired);
+    }
+
+    // Read fields
+
+    let description = read_from_config_value(&mut validation_errors, toml_config.description, data);
+
+    let mut program_paths = vec![];
+    let mut steps = vec![];
+    for step_config in step_configs {
+        let (program_path, step) =
+            build_test_step(&mut validation_errors, step_config, &current_dir, data);
+        program_paths.push(program_path);
+        steps.extend(step);
+    }
+
+    let setup = read_hook_commands(
+        &mut validation_errors,
+        toml_config.setup,
         &current_dir.to_logical_path("."),
+        data,
+    );
+    let teardown = read_hook_commands(
+        &mut validation_errors,
+        toml_config.teardown,
+        &current_dir.to_logical_path("."),
+        data,
+    );
+
+    let mut expected_files = BTreeMap::new();
+    for (path, expected_file) in toml_config.expected_files.unwrap_or_default() {
+        if !is_valid_expected_file_path(&path) {
+            validation_errors.insert(TestCaseValidationError::InvalidExpectedFilePath(path));
+            continue;
+        }
+
+        match expected_file {
+            ExpectedFile::Exists { unknown_fields, .. } if !unknown_fields.is_empty() => {
+                check_unknown_fields(&mut validation_errors, &unknown_fields, &["exists"], "");
+            }
+            ExpectedFile::Exists { exists: false, .. } => {
+                expected_files.insert(path, None);
+            }
+            ExpectedFile::Exists { exists: true, .. } => {
+                validation_errors
+                    .insert(TestCaseValidationError::ExpectedFileContentRequired(path));
+            }
+            ExpectedFile::Content(content) => match content.read(data) {
+                Ok(content) => {
+                    expected_files.insert(path, Some(content));
+                }
+                Err(err) => {
+                    validation_errors.insert(err);
+                }
+            },
+        }
+    }
+
+    let expected_dir = toml_config
+        .expected_dir
+        .map(|path| current_dir.join_normalized(path));
+
+    // Sessions and tests that inspect the files created by the program are run
+    // inside an empty temporary directory. The commands in a Markdown file
+    // refer to files next to it, also in blocks with multiple commands.
+    let is_markdown = ConfigFormat::from_path(&source_file) == ConfigFormat::Markdown;
+    let sandbox =
+        (is_session && !is_markdown) || !expected_files.is_empty() || expected_dir.is_some();
+
+    let test_case = if validation_errors.is_empty() {
+        Ok(TestCase {
+            source_file,
+            id,
+            description,
+            steps,
+            is_session,
+            sandbox,
+            once_setup,
+            setup,
+            teardown,
+            expected_files,
+            expected_dir,
+            retries: toml_config.retries,
+            exclusive: toml_config.exclusive.unwrap_or(false),
+            lock: toml_config.lock,
+            check_deterministic: toml_config.check_deterministic.unwrap_or(false),
+        })
+    } else {
+        Err(validation_errors)
+    };
+
+    TestDetails {
+        definition,
+        requirements,
+        program_paths,
+        test_case,
+        error_locations: BTreeMap::new(),
+    }
+}
+
+// Returns `None` if the step contains validation errors
+fn build_test_step(
+    validation_errors: &mut BTreeSet<TestCaseValidationError>,
+    step_config: TomlStepConfig,
+    current_dir: &RelativePath,
+    data: &TomlConfigData,
+) -> (ProgramPath, Option<TestStep>) {
+    let in_dir = current_dir.to_logical_path(".");
+    let mut step_validation_errors = BTreeSet::new();
+
+    check_unknown_fields(
+        &mut step_validation_errors,
+        &step_config.unknown_fields,
+        TOML_STEP_CONFIG_FIELDS,
+        "",
     );
+
+    // Program path
+    let program = read_from_config_value(&mut step_validation_errors, step_config.program, data);
+    let program_path = get_program_path(program.unwrap_or_default(), &in_dir);
     match &program_path {
         ProgramPath::NotSpecified => {
-            validation_errors.insert(TestCaseValidationError::ProgramRequired);
+            step_validation_errors.insert(TestCaseValidationError::ProgramRequired);
         }
         ProgramPath::MissingProgram { requested_path } => {
-            validation_errors.insert(TestCaseValidationError::ProgramNotFound(
+            step_validation_errors.insert(TestCaseValidationError::ProgramNotFound(
                 requested_path.clone(),
             ));
         }
@@ -247,64 +834,240 @@ fn build_test_details(
         } => {}
     }
 
-    // Validate fields in config file
+    // Read fields
 
-    if toml_config.expected_stdout.is_none()
-        && toml_config.expected_stderr.is_none()
-        && toml_config.expected_exit_code.is_none()
-    {
-        validation_errors.insert(TestCaseValidationError::ExpectationRequired);
-    }
+    let description =
+        read_from_config_value(&mut step_validation_errors, step_config.description, data);
 
-    // Read fields
+    let arguments = read_from_config_values(
+        &mut step_validation_errors,
+        step_config.program_arguments,
+        data,
+    );
 
-    let description = read_from_config_value(&mut validation_errors, toml_config.description, data);
+    let stdin = read_from_config_value(&mut step_validation_errors, step_config.stdin, data);
 
-    let mut arguments = vec![];
-    for arg in toml_config.program_arguments.unwrap_or_default() {
-        match arg.read(data) {
-            Ok(arg) => {
-                arguments.push(arg);
-            }
-            Err(err) => {
-                validation_errors.insert(err);
-            }
-        }
+    let pty = step_config.pty.unwrap_or(false);
+    let interaction = read_interaction(
+        &mut step_validation_errors,
+        step_config.interaction.unwrap_or_default(),
+        data,
+    );
+    if !pty && !interaction.is_empty() {
+        step_validation_errors.insert(TestCaseValidationError::InteractionRequiresPty);
     }
 
-    let stdin = read_from_config_value(&mut validation_errors, toml_config.stdin, data);
+    let (expected_stdout, stdout_is_json) =
+        read_expected_output(&mut step_validation_errors, step_config.expected_stdout);
+    let (expected_stderr, stderr_is_json) =
+        read_expected_output(&mut step_validation_errors, step_config.expected_stderr);
+
+    // External files are updated by `--accept`
+    let expected_stdout_file = get_external_file(current_dir, &expected_stdout);
+    let expected_stderr_file = get_external_file(current_dir, &expected_stderr);
 
     let expected_stdout =
-        read_from_config_value(&mut validation_errors, toml_config.expected_stdout, data);
+        read_from_config_value(&mut step_validation_errors, expected_stdout, data);
     let expected_stderr =
-        read_from_config_value(&mut validation_errors, toml_config.expected_stderr, data);
-    let expected_exit_code =
-        read_from_config_value(&mut validation_errors, toml_config.expected_exit_code, data);
+        read_from_config_value(&mut step_validation_errors, expected_stderr, data);
+    let expected_exit_code = read_from_config_value(
+        &mut step_validation_errors,
+        step_config.expected_exit_code,
+        data,
+    );
 
-    let test_case = if validation_errors.is_empty() {
+    let mut assertions = vec![];
+    assertions.extend(
+        read_from_config_values(
+            &mut step_validation_errors,
+            step_config.stdout_contains,
+            data,
+        )
+        .into_iter()
+        .map(Assertion::StdoutContains),
+    );
+    assertions.extend(
+        read_from_config_values(
+            &mut step_validation_errors,
+            step_config.stdout_not_contains,
+            data,
+        )
+        .into_iter()
+        .map(Assertion::StdoutNotContains),
+    );
+    assertions.extend(
+        read_from_config_values(
+            &mut step_validation_errors,
+            step_config.stderr_contains,
+            data,
+        )
+        .into_iter()
+        .map(Assertion::StderrContains),
+    );
+    assertions.extend(
+        read_from_config_values(
+            &mut step_validation_errors,
+            step_config.stderr_not_contains,
+            data,
+        )
+        .into_iter()
+        .map(Assertion::StderrNotContains),
+    );
+
+    let max_duration = step_config.max_duration.and_then(|max_duration| {
+        match duration::parse_duration(&max_duration) {
+            Some(max_duration) => Some(max_duration),
+            None => {
+                step_validation_errors
+                    .insert(TestCaseValidationError::InvalidDuration(max_duration));
+                None
+            }
+        }
+    });
+
+    // `stdout_compare` and `stderr_compare` override `compare` for a single stream
+    let compare = read_compare_mode(&mut step_validation_errors, step_config.compare);
+    let stdout_compare = match step_config.stdout_compare {
+        Some(stdout_compare) => {
+            read_compare_mode(&mut step_validation_errors, Some(stdout_compare))
+        }
+        None => compare.clone(),
+    };
+    let stderr_compare = match step_config.stderr_compare {
+        Some(stderr_compare) => {
+            read_compare_mode(&mut step_validation_errors, Some(stderr_compare))
+        }
+        None => compare,
+    };
+    let stdout_compare = compare_as_json(stdout_is_json, stdout_compare);
+    let stderr_compare = compare_as_json(stderr_is_json, stderr_compare);
+
+    let stdout_filter = step_config
+        .stdout_filter
+        .and_then(|command| read_hook_command(&mut step_validation_errors, command, &in_dir, data));
+    let stderr_filter = step_config
+        .stderr_filter
+        .and_then(|command| read_hook_command(&mut step_validation_errors, command, &in_dir, data));
+
+    let step = if step_validation_errors.is_empty() {
         let program = program_path
             .get_resolved_path()
             .expect("Validation errors should not be empty if program path is not resolved");
 
-        Ok(TestCase {
-            source_file,
-            id,
+        Some(TestStep {
             description,
             program,
             arguments,
             stdin,
+            pty,
+            interaction,
             expected_stdout,
             expected_stderr,
+            expected_stdout_file,
+            expected_stderr_file,
             expected_exit_code,
+            assertions,
+            max_duration,
+            stdout_compare,
+            stderr_compare,
+            stdout_filter,
+            stderr_filter,
         })
     } else {
-        Err(validation_errors)
+        validation_errors.extend(step_validation_errors);
+        None
     };
 
-    TestDetails {
-        requirements,
-        program_path,
-        test_case,
+    (program_path, step)
+}
+
+// Steps inherit all fields from the test, except `description`. Tests without
+// `steps` consist of a single step.
+fn get_step_configs(config: &TomlConfig) -> Vec<TomlStepConfig> {
+    let base_step_config = TomlStepConfig {
+        description: None,
+        program: config.program.clone(),
+        program_arguments: config.program_arguments.clone(),
+        stdin: config.stdin.clone(),
+        pty: config.pty,
+        interaction: config.interaction.clone(),
+        expected_stdout: config.expected_stdout.clone(),
+        expected_stderr: config.expected_stderr.clone(),
+        expected_exit_code: config.expected_exit_code.clone(),
+        stdout_contains: config.stdout_contains.clone(),
+        stdout_not_contains: config.stdout_not_contains.clone(),
+        stderr_contains: config.stderr_contains.clone(),
+        stderr_not_contains: config.stderr_not_contains.clone(),
+        max_duration: config.max_duration.clone(),
+        compare: config.compare.clone(),
+        stdout_compare: config.stdout_compare.clone(),
+        stderr_compare: config.stderr_compare.clone(),
+        stdout_filter: config.stdout_filter.clone(),
+        stderr_filter: config.stderr_filter.clone(),
+        unknown_fields: BTreeMap::new(), // Reported for the test itself
+    };
+
+    match &config.steps {
+        Some(steps) => steps
+            .iter()
+            .map(|step| merge_step_configs(base_step_config.clone(), step.clone()))
+            .collect(),
+        None => vec![base_step_config],
+    }
+}
+
+fn merge_step_configs(
+    base_config: TomlStepConfig,
+    prioritized_config: TomlStepConfig,
+) -> TomlStepConfig {
+    TomlStepConfig {
+        description: prioritized_config.description.or(base_config.description),
+        program: prioritized_config.program.or(base_config.program),
+        program_arguments: prioritized_config
+            .program_arguments
+            .or(base_config.program_arguments),
+        stdin: prioritized_config.stdin.or(base_config.stdin),
+        pty: prioritized_config.pty.or(base_config.pty),
+        interaction: prioritized_config.interaction.or(base_config.interaction),
+        expected_stdout: prioritized_config
+            .expected_stdout
+            .or(base_config.expected_stdout),
+        expected_stderr: prioritized_config
+            .expected_stderr
+            .or(base_config.expected_stderr),
+        expected_exit_code: prioritized_config
+            .expected_exit_code
+            .or(base_config.expected_exit_code),
+        stdout_contains: prioritized_config
+            .stdout_contains
+            .or(base_config.stdout_contains),
+        stdout_not_contains: prioritized_config
+            .stdout_not_contains
+            .or(base_config.stdout_not_contains),
+        stderr_contains: prioritized_config
+            .stderr_contains
+            .or(base_config.stderr_contains),
+        stderr_not_contains: prioritized_config
+            .stderr_not_contains
+            .or(base_config.stderr_not_contains),
+        max_duration: prioritized_config.max_duration.or(base_config.max_duration),
+        compare: prioritized_config.compare.or(base_config.compare),
+        stdout_compare: prioritized_config
+            .stdout_compare
+            .or(base_config.stdout_compare),
+        stderr_compare: prioritized_config
+            .stderr_compare
+            .or(base_config.stderr_compare),
+        stdout_filter: prioritized_config
+            .stdout_filter
+            .or(base_config.stdout_filter),
+        stderr_filter: prioritized_config
+            .stderr_filter
+            .or(base_config.stderr_filter),
+        unknown_fields: merge_unknown_fields(
+            base_config.unknown_fields,
+            prioritized_config.unknown_fields,
+        ),
     }
 }
 
@@ -323,6 +1086,253 @@ fn get_program_path(requested_path: String, in_dir: &Path) -> ProgramPath {
     }
 }
 
+fn read_hook_commands(
+    validation_errors: &mut BTreeSet<TestCaseValidationError>,
+    commands: Option<Vec<Vec<ConfigValue<String>>>>,
+    in_dir: &Path,
+    data: &TomlConfigData,
+) -> Vec<HookCommand> {
+    commands
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|command| read_hook_command(validation_errors, command, in_dir, data))
+        .collect()
+}
+
+// Returns `None` if the command contains validation errors
+fn read_hook_command(
+    validation_errors: &mut BTreeSet<TestCaseValidationError>,
+    command: Vec<ConfigValue<String>>,
+    in_dir: &Path,
+    data: &TomlConfigData,
+) -> Option<HookCommand> {
+    let mut values = read_from_config_values(validation_errors, Some(command), data).into_iter();
+    let Some(program_name) = values.next() else {
+        validation_errors.insert(TestCaseValidationError::HookCommandRequired);
+        return None;
+    };
+
+    match get_program_path(program_name.clone(), in_dir) {
+        ProgramPath::ResolvedPath {
+            requested_path: _,
+            resolved_path,
+        } => Some(HookCommand {
+            program_name,
+            program: resolved_path,
+            arguments: values.collect(),
+        }),
+        ProgramPath::NotSpecified => {
+            validation_errors.insert(TestCaseValidationError::HookCommandRequired);
+            None
+        }
+        ProgramPath::MissingProgram { requested_path } => {
+            validation_errors.insert(TestCaseValidationError::ProgramNotFound(requested_path));
+            None
+        }
+    }
+}
+
+fn get_external_file<T>(
+    current_dir: &RelativePath,
+    config_value: &Option<ConfigValue<T>>,
+) -> Option<RelativePathBuf> {
+    match config_value {
+        Some(ConfigValue::ReadFromFile { file, .. }) => Some(current_dir.join_normalized(file)),
+        _ => None,
+    }
+}
+
+fn check_unknown_fields(
+    validation_errors: &mut BTreeSet<TestCaseValidationError>,
+    unknown_fields: &BTreeMap<String, IgnoredAny>,
+    known_fields: &[&str],
+    prefix: &str, // Name of the table containing the fields, e.g. `once.`
+) {
+    for field in unknown_fields.keys() {
+        let suggestion = string::find_closest_match(field, known_fields)
+            .map(|suggestion| format!("{}{}", prefix, suggestion));
+        validation_errors.insert(TestCaseValidationError::UnknownField {
+            field: format!("{}{}", prefix, field),
+            suggestion,
+        });
+    }
+}
+
+const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn read_interaction(
+    validation_errors: &mut BTreeSet<TestCaseValidationError>,
+    toml_interaction: Vec<TomlInteraction>,
+    data: &TomlConfigData,
+) -> Vec<Interaction> {
+    let mut interaction = vec![];
+
+    for toml_interaction in toml_interaction {
+        match toml_interaction {
+            TomlInteraction::Expect {
+                expect,
+                timeout,
+                unknown_fields,
+            } => {
+                check_unknown_fields(
+                    validation_errors,
+                    &unknown_fields,
+                    &["expect", "timeout"],
+                    "",
+                );
+
+                let timeout = match timeout {
+                    Some(timeout) => match duration::parse_duration(&timeout) {
+                        Some(timeout) => timeout,
+                        None => {
+                            validation_errors
+                                .insert(TestCaseValidationError::InvalidDuration(timeout));
+                            continue;
+                        }
+                    },
+                    None => DEFAULT_EXPECT_TIMEOUT,
+                };
+
+                match expect.read(data) {
+                    Ok(text) => interaction.push(Interaction::Expect { text, timeout }),
+                    Err(err) => {
+                        validation_errors.insert(err);
+                    }
+                }
+            }
+            TomlInteraction::Send {
+                send,
+                unknown_fields,
+            } => {
+                check_unknown_fields(validation_errors, &unknown_fields, &["send"], "");
+
+                match send.read(data) {
+                    Ok(text) => interaction.push(Interaction::Send(text)),
+                    Err(err) => {
+                        validation_errors.insert(err);
+                    }
+                }
+            }
+        }
+    }
+
+    interaction
+}
+
+// Returns the expected output, and whether it should be compared as JSON
+fn read_expected_output(
+    validation_errors: &mut BTreeSet<TestCaseValidationError>,
+    expected_output: Option<TomlExpectedOutput>,
+) -> (Option<ConfigValue<String>>, bool) {
+    match expected_output {
+        None => (None, false),
+        Some(TomlExpectedOutput::Text(value)) => (Some(value), false),
+        Some(TomlExpectedOutput::Json {
+            json,
+            unknown_fields,
+        }) => {
+            check_unknown_fields(validation_errors, &unknown_fields, &["json"], "");
+            (Some(json), true)
+        }
+    }
+}
+
+// JSON pointers to ignore are kept if the stream is already compared as JSON
+fn compare_as_json(is_json: bool, compare: CompareMode) -> CompareMode {
+    if is_json && !matches!(compare, CompareMode::Json { .. }) {
+        CompareMode::Json {
+            ignored_paths: vec![],
+        }
+    } else {
+        compare
+    }
+}
+
+fn read_compare_mode(
+    validation_errors: &mut BTreeSet<TestCaseValidationError>,
+    compare: Option<TomlCompare>,
+) -> CompareMode {
+    match compare {
+        None => CompareMode::Exact,
+        Some(TomlCompare::Mode(mode)) => match mode.as_str() {
+            "exact" => CompareMode::Exact,
+            "json" => CompareMode::Json {
+                ignored_paths: vec![],
+            },
+            "unordered-lines" => CompareMode::UnorderedLines,
+            _ => {
+                validation_errors.insert(TestCaseValidationError::InvalidCompareMode(mode));
+                CompareMode::Exact
+            }
+        },
+        Some(TomlCompare::Json {
+            json,
+            unknown_fields,
+        }) => {
+            check_unknown_fields(validation_errors, &unknown_fields, &["json"], "");
+            check_unknown_fields(validation_errors, &json.unknown_fields, &["ignore"], "");
+
+            let ignored_paths = json.ignore.unwrap_or_default();
+            for path in &ignored_paths {
+                if !path.is_empty() && !path.starts_with('/') {
+                    validation_errors
+                        .insert(TestCaseValidationError::InvalidJsonPointer(path.clone()));
+                }
+            }
+            CompareMode::Json { ignored_paths }
+        }
+        Some(TomlCompare::NumericTolerance {
+            numeric_tolerance,
+            unknown_fields,
+        }) => {
+            check_unknown_fields(
+                validation_errors,
+                &unknown_fields,
+                &["numeric_tolerance"],
+                "",
+            );
+
+            let (absolute, relative) = match numeric_tolerance {
+                TomlNumericTolerance::Absolute(absolute) => (absolute, 0.0),
+                TomlNumericTolerance::Table {
+                    absolute,
+                    relative,
+                    unknown_fields,
+                } => {
+                    check_unknown_fields(
+                        validation_errors,
+                        &unknown_fields,
+                        &["absolute", "relative"],
+                        "",
+                    );
+                    (absolute.unwrap_or(0.0), relative.unwrap_or(0.0))
+                }
+            };
+            for tolerance in [absolute, relative] {
+                if !(tolerance >= 0.0 && tolerance.is_finite()) {
+                    validation_errors.insert(TestCaseValidationError::InvalidNumericTolerance(
+                        tolerance.to_string(),
+                    ));
+                }
+            }
+            CompareMode::NumericTolerance { absolute, relative }
+        }
+    }
+}
+
+// Expected files are checked inside the sandbox, so they may not point outside of it
+fn is_valid_expected_file_path(path: &str) -> bool {
+    let Ok(relative_path) = RelativePathBuf::from_path(path) else {
+        return false;
+    };
+
+    let normalized_path = relative_path.normalize();
+    match normalized_path.components().next() {
+        Some(Component::Normal(_)) => true,
+        Some(Component::ParentDir) | Some(Component::CurDir) | None => false,
+    }
+}
+
 fn read_from_config_value<T>(
     validation_errors: &mut BTreeSet<TestCaseValidationError>,
     config_value: Option<ConfigValue<T>>,
@@ -344,6 +1354,28 @@ where
     }
 }
 
+fn read_from_config_values<T>(
+    validation_errors: &mut BTreeSet<TestCaseValidationError>,
+    config_values: Option<Vec<ConfigValue<T>>>,
+    data: &TomlConfigData,
+) -> Vec<T>
+where
+    T: FromStr,
+{
+    let mut values = vec![];
+
+    for config_value in config_values.unwrap_or_default() {
+        match config_value.read(data) {
+            Ok(value) => values.push(value),
+            Err(err) => {
+                validation_errors.insert(err);
+            }
+        }
+    }
+
+    values
+}
+
 // Currently only merges a single level
 fn split_toml_config(base_config: TomlConfig) -> BTreeMap<TestId, TomlConfig> {
     if let Some(tests) = base_config.tests.clone() {
@@ -368,6 +1400,10 @@ fn merge_toml_configs(base_config: TomlConfig, prioritized_config: TomlConfig) -
             .program_arguments
             .or(base_config.program_arguments),
         stdin: prioritized_config.stdin.or(base_config.stdin),
+        pty: prioritized_config.pty.or(base_config.pty),
+        interaction: prioritized_config.interaction.or(base_config.interaction),
+        setup: prioritized_config.setup.or(base_config.setup),
+        teardown: prioritized_config.teardown.or(base_config.teardown),
         expected_stdout: prioritized_config
             .expected_stdout
             .or(base_config.expected_stdout),
@@ -377,7 +1413,476 @@ fn merge_toml_configs(base_config: TomlConfig, prioritized_config: TomlConfig) -
         expected_exit_code: prioritized_config
             .expected_exit_code
             .or(base_config.expected_exit_code),
+        stdout_contains: prioritized_config
+            .stdout_contains
+            .or(base_config.stdout_contains),
+        stdout_not_contains: prioritized_config
+            .stdout_not_contains
+            .or(base_config.stdout_not_contains),
+        stderr_contains: prioritized_config
+            .stderr_contains
+            .or(base_config.stderr_contains),
+        stderr_not_contains: prioritized_config
+            .stderr_not_contains
+            .or(base_config.stderr_not_contains),
+        max_duration: prioritized_config.max_duration.or(base_config.max_duration),
+        compare: prioritized_config.compare.or(base_config.compare),
+        stdout_compare: prioritized_config
+            .stdout_compare
+            .or(base_config.stdout_compare),
+        stderr_compare: prioritized_config
+            .stderr_compare
+            .or(base_config.stderr_compare),
+        stdout_filter: prioritized_config
+            .stdout_filter
+            .or(base_config.stdout_filter),
+        stderr_filter: prioritized_config
+            .stderr_filter
+            .or(base_config.stderr_filter),
+        expected_files: prioritized_config
+            .expected_files
+            .or(base_config.expected_files),
+        expected_dir: prioritized_config.expected_dir.or(base_config.expected_dir),
+        steps: prioritized_config.steps.or(base_config.steps),
+        retries: prioritized_config.retries.or(base_config.retries),
+        exclusive: prioritized_config.exclusive.or(base_config.exclusive),
+        lock: prioritized_config.lock.or(base_config.lock),
+        check_deterministic: prioritized_config
+            .check_deterministic
+            .or(base_config.check_deterministic),
         tests: prioritized_config.tests, // Do not propagate tests from `base_config`
+        unknown_fields: merge_unknown_fields(
+            base_config.unknown_fields,
+            prioritized_config.unknown_fields,
+        ),
+    }
+}
+
+// Unknown fields from both configs are reported
+fn merge_unknown_fields(
+    mut base_fields: BTreeMap<String, IgnoredAny>,
+    prioritized_fields: BTreeMap<String, IgnoredAny>,
+) -> BTreeMap<String, IgnoredAny> {
+    base_fields.extend(prioritized_fields);
+    base_fields
+}
+
+// MARKDOWN
+
+// Each console block is converted to a test, where each command is a step
+fn markdown_to_config(content: &str) -> Result<Mapping, ConfigError> {
+    let mut tests = Mapping::new();
+
+    for block in markdown::parse_console_blocks(content) {
+        let mut steps = vec![];
+        for command in block.commands {
+            let Some(words) = string::split_command(&command.line) else {
+                return Err(format_error(
+                    format!("Unterminated quote in command `{}`", command.line),
+                    SourceLocation::from_line_col(content, block.line - 1, 0),
+                ));
+            };
+
+            let mut words = words.into_iter().map(YamlValue::from);
+            let mut step = Mapping::new();
+            step.insert("program".into(), words.next().unwrap_or_default());
+            step.insert(
+                "program_arguments".into(),
+                YamlValue::Sequence(words.collect()),
+            );
+            step.insert("expected_stdout".into(), command.expected_output.into());
+            // Commands are expected to succeed
+            step.insert("expected_exit_code".into(), YamlValue::from(0));
+            steps.push(step);
+        }
+
+        // Blocks without commands only show output
+        let Some(last_step) = steps.last_mut() else {
+            continue;
+        };
+
+        // The `exit_code` attribute applies to the last command of the block
+        if let Some(exit_code) = block.attributes.get("exit_code") {
+            let expected_exit_code = exit_code
+                .parse::<i32>()
+                .map_or_else(|_| YamlValue::from(exit_code.as_str()), YamlValue::from);
+            last_step.insert("expected_exit_code".into(), expected_exit_code);
+        }
+
+        let test = match <[Mapping; 1]>::try_from(steps) {
+            Ok([step]) => step,
+            Err(steps) => Mapping::from_iter([(
+                "steps".into(),
+                YamlValue::Sequence(steps.into_iter().map(YamlValue::Mapping).collect()),
+            )]),
+        };
+
+        tests.insert(block.name.into(), YamlValue::Mapping(test));
+    }
+
+    Ok(Mapping::from_iter([(
+        "tests".into(),
+        YamlValue::Mapping(tests),
+    )]))
+}
+
+// SOURCE LOCATIONS
+
+// Prefer syntax errors from `toml_edit`, since they include the exact location
+fn parse_error(source: &str, error: toml::de::Error) -> ConfigError {
+    let document = match ImDocument::parse(source) {
+        Ok(document) => document,
+        Err(syntax_error) => {
+            return ConfigError::FailedToParseConfig {
+                message: syntax_error.message().trim_end().to_owned(),
+                location: syntax_error
+                    .span()
+                    .and_then(|span| SourceLocation::from_span(source, span)),
+            };
+        }
+    };
+
+    let invalid_field = toml::from_str::<JsonValue>(source)
+        .ok()
+        .and_then(|root| find_invalid_field(&root));
+
+    let location = match invalid_field {
+        Some(field_path) => find_key_span(document.as_table(), &field_path)
+            .and_then(|span| SourceLocation::from_span(source, span)),
+        None => error
+            .line_col()
+            .and_then(|(line, column)| SourceLocation::from_line_col(source, line, column)),
+    };
+
+    format_error(error.to_string(), location)
+}
+
+pub(crate) fn format_error(message: String, location: Option<SourceLocation>) -> ConfigError {
+    // The location is shown separately
+    let message = if location.is_some() {
+        strip_location(message)
+    } else {
+        message
+    };
+
+    ConfigError::FailedToParseConfig { message, location }
+}
+
+fn strip_location(mut message: String) -> String {
+    if let Some(index) = message.rfind(" at line ") {
+        message.truncate(index);
+    }
+    message
+}
+
+// Errors in flattened fields are reported without a location, so the invalid
+// field is found by deserializing each field separately. All formats can be
+// represented as JSON values.
+fn find_invalid_field(root: &JsonValue) -> Option<Vec<String>> {
+    let is_valid = |field_path: &[&str], value: &JsonValue| {
+        let nested_value = field_path.iter().rev().fold(value.clone(), |value, field| {
+            JsonValue::Object(JsonMap::from_iter([(field.to_string(), value)]))
+        });
+        serde_json::from_value::<TomlConfigFile>(nested_value).is_ok()
+    };
+
+    for (field, value) in root.as_object()? {
+        if let (Some(tests), "tests") = (value.as_object(), field.as_str()) {
+            for (name, test) in tests {
+                for (test_field, test_value) in test.as_object().into_iter().flatten() {
+                    if !is_valid(&[field, name, test_field], test_value) {
+                        return Some(vec![field.clone(), name.clone(), test_field.clone()]);
+                    }
+                }
+            }
+        } else if !is_valid(&[field], value) {
+            return Some(vec![field.clone()]);
+        }
+    }
+
+    None
+}
+
+// The YAML and JSON parsers do not keep the locations of keys, so the source is
+// searched for the invalid field instead
+fn yaml_or_json_error(
+    source: &str,
+    message: String,
+    location: Option<SourceLocation>,
+) -> ConfigError {
+    let Some(field_path) = serde_yaml::from_str::<JsonValue>(source)
+        .ok()
+        .and_then(|root| find_invalid_field(&root))
+    else {
+        return format_error(message, location);
+    };
+
+    match find_key_in_text(source, &field_path)
+        .and_then(|span| SourceLocation::from_span(source, span))
+    {
+        Some(location) => format_error(message, Some(location)),
+        None => format_error(
+            format!(
+                "{} for key `{}`",
+                strip_location(message),
+                field_path.join(".")
+            ),
+            None,
+        ),
+    }
+}
+
+// Each key of the path is searched for after the previous one, e.g. the name
+// of a test after `tests`. Keys may be quoted, and are followed by a colon.
+fn find_key_in_text(source: &str, field_path: &[String]) -> Option<Range<usize>> {
+    let mut span = None;
+    let mut start = 0;
+
+    for field in field_path {
+        let index = source[start..]
+            .match_indices(field.as_str())
+            .map(|(index, _)| start + index)
+            .find(|&index| is_key_at(source, index, field))?;
+        span = Some(index..index + field.len());
+        start = index + field.len();
+    }
+
+    span
+}
+
+fn is_key_at(source: &str, index: usize, key: &str) -> bool {
+    let before = source[..index].chars().next_back();
+    let after = &source[index + key.len()..];
+    let after = after.strip_prefix(['"', '\'']).unwrap_or(after);
+
+    before.is_none_or(|x| x.is_whitespace() || matches!(x, '"' | '\'' | '{' | ','))
+        && after.trim_start_matches([' ', '\t']).starts_with(':')
+}
+
+fn find_key_span(table: &Table, field_path: &[String]) -> Option<Range<usize>> {
+    let (field, rest) = field_path.split_first()?;
+    let (key, item) = table.get_key_value(field)?;
+
+    if rest.is_empty() {
+        key.span()
+    } else {
+        find_key_span(item.as_table()?, rest)
+    }
+}
+
+fn locate_validation_errors(
+    document: &ImDocument<&str>,
+    test_id: &TestId,
+    validation_errors: &BTreeSet<TestCaseValidationError>,
+) -> BTreeMap<TestCaseValidationError, SourceLocation> {
+    let root = document.as_table();
+
+    // Fields in the test itself take precedence over inherited fields
+    let mut tables = vec![];
+    if let Some(name) = test_id.clone().id_path().first() {
+        if let Some(test_table) = root
+            .get("tests")
+            .and_then(|x| x.get(name))
+            .and_then(|x| x.as_table())
+        {
+            tables.push(test_table);
+        }
+    }
+    tables.push(root);
+
+    let mut locations = BTreeMap::new();
+
+    for validation_error in validation_errors {
+        let span = match validation_error {
+            TestCaseValidationError::UnknownField { field, .. } => {
+                if let Some(field) = field.strip_prefix("once.") {
+                    root.get("once")
+                        .and_then(|x| x.as_table())
+                        .and_then(|x| find_span_in_table(x, &|key, _| key_span(key, field)))
+                } else {
+                    find_span(&tables, &|key, _| key_span(key, field))
+                }
+            }
+            TestCaseValidationError::MissingExternalFile(file) => {
+                find_span(&tables, &|key, value| {
+                    string_span(key, value, &["file"], file)
+                })
+            }
+            TestCaseValidationError::MissingEnvVar(var_name) => {
+                find_span(&tables, &|key, value| {
+                    string_span(key, value, &["env"], var_name)
+                })
+            }
+            TestCaseValidationError::ProgramNotFound(program) => {
+                find_span(&tables, &|key, value| {
+                    string_span(
+                        key,
+                        value,
+                        &[
+                            "program",
+                            "setup",
+                            "teardown",
+                            "stdout_filter",
+                            "stderr_filter",
+                        ],
+                        program,
+                    )
+                })
+            }
+            TestCaseValidationError::InvalidExpectedFilePath(path)
+            | TestCaseValidationError::ExpectedFileContentRequired(path) => {
+                find_span(&tables, &|key, _| key_span(key, path))
+            }
+            TestCaseValidationError::InvalidDuration(duration) => {
+                find_span(&tables, &|key, value| {
+                    string_span(key, value, &["timeout", "max_duration"], duration)
+                })
+            }
+            TestCaseValidationError::InteractionRequiresPty => {
+                find_span(&tables, &|key, _| key_span(key, "interaction"))
+            }
+            TestCaseValidationError::InvalidCompareMode(mode) => {
+                find_span(&tables, &|key, value| {
+                    string_span(
+                        key,
+                        value,
+                        &["compare", "stdout_compare", "stderr_compare"],
+                        mode,
+                    )
+                })
+            }
+            TestCaseValidationError::InvalidJsonPointer(path) => {
+                find_span(&tables, &|key, value| {
+                    string_span(key, value, &["ignore"], path)
+                })
+            }
+            TestCaseValidationError::InvalidNumericTolerance(tolerance) => {
+                find_span(&tables, &|key, value| {
+                    number_span(
+                        key,
+                        value,
+                        &["numeric_tolerance", "absolute", "relative"],
+                        tolerance,
+                    )
+                })
+            }
+            TestCaseValidationError::HookCommandRequired => find_span(&tables, &|key, value| {
+                empty_array_span(
+                    key,
+                    value,
+                    &["setup", "teardown", "stdout_filter", "stderr_filter"],
+                )
+            }),
+            TestCaseValidationError::StepRequired => find_span(&tables, &|key, value| {
+                empty_array_span(key, value, &["steps"])
+            }),
+            TestCaseValidationError::FailedToParseString
+            | TestCaseValidationError::ProgramRequired
+            | TestCaseValidationError::ExpectationRequired => None,
+        };
+
+        if let Some(location) = span.and_then(|x| SourceLocation::from_span(document.raw(), x)) {
+            locations.insert(validation_error.clone(), location);
+        }
+    }
+
+    locations
+}
+
+// The value is `None` for tables
+type SpanPredicate<'a> = dyn Fn(&Key, Option<&Value>) -> Option<Range<usize>> + 'a;
+
+fn find_span(tables: &[&Table], predicate: &SpanPredicate) -> Option<Range<usize>> {
+    tables.iter().find_map(|table| {
+        // Other tests are not searched
+        let fields = table.iter().filter(|(name, _)| *name != "tests");
+        find_span_in_fields(table, fields, predicate)
+    })
+}
+
+fn find_span_in_table(table: &Table, predicate: &SpanPredicate) -> Option<Range<usize>> {
+    find_span_in_fields(table, table.iter(), predicate)
+}
+
+fn find_span_in_fields<'a>(
+    table: &'a Table,
+    mut fields: impl Iterator<Item = (&'a str, &'a Item)>,
+    predicate: &SpanPredicate,
+) -> Option<Range<usize>> {
+    fields.find_map(|(name, item)| {
+        let key = table.key(name)?;
+        match item {
+            Item::None => None,
+            Item::Value(value) => find_span_in_value(key, value, predicate),
+            Item::Table(table) => {
+                predicate(key, None).or_else(|| find_span_in_table(table, predicate))
+            }
+            Item::ArrayOfTables(tables) => predicate(key, None)
+                .or_else(|| tables.iter().find_map(|x| find_span_in_table(x, predicate))),
+        }
+    })
+}
+
+// Values in arrays are associated with the key of the array
+fn find_span_in_value(key: &Key, value: &Value, predicate: &SpanPredicate) -> Option<Range<usize>> {
+    predicate(key, Some(value)).or_else(|| match value {
+        Value::Array(array) => array
+            .iter()
+            .find_map(|x| find_span_in_value(key, x, predicate)),
+        Value::InlineTable(table) => table
+            .iter()
+            .find_map(|(name, x)| find_span_in_value(table.key(name)?, x, predicate)),
+        _ => None,
+    })
+}
+
+fn key_span(key: &Key, name: &str) -> Option<Range<usize>> {
+    if key.get() == name {
+        key.span()
+    } else {
+        None
+    }
+}
+
+fn string_span(
+    key: &Key,
+    value: Option<&Value>,
+    key_names: &[&str],
+    expected: &str,
+) -> Option<Range<usize>> {
+    let value = value?;
+    if key_names.contains(&key.get()) && value.as_str() == Some(expected) {
+        value.span()
+    } else {
+        None
+    }
+}
+
+// Integers are accepted where a float is expected
+fn number_span(
+    key: &Key,
+    value: Option<&Value>,
+    key_names: &[&str],
+    expected: &str,
+) -> Option<Range<usize>> {
+    let value = value?;
+    let number = value
+        .as_float()
+        .or_else(|| value.as_integer().map(|x| x as f64));
+    if key_names.contains(&key.get()) && number.map(|x| x.to_string()).as_deref() == Some(expected)
+    {
+        value.span()
+    } else {
+        None
+    }
+}
+
+fn empty_array_span(key: &Key, value: Option<&Value>, key_names: &[&str]) -> Option<Range<usize>> {
+    let value = value?;
+    if key_names.contains(&key.get()) && value.as_array().is_some_and(|x| x.is_empty()) {
+        value.span()
+    } else {
+        None
     }
 }
 
@@ -387,9 +1892,28 @@ where
 {
     fn read(self, data: &TomlConfigData) -> Result<T, TestCaseValidationError> {
         match self {
+            Self::WrappedLiteral { unknown_fields, .. }
+            | Self::ReadFromFile { unknown_fields, .. }
+            | Self::FetchFromEnv { unknown_fields, .. }
+                if !unknown_fields.is_empty() =>
+            {
+                let mut validation_errors = BTreeSet::new();
+                check_unknown_fields(
+                    &mut validation_errors,
+                    &unknown_fields,
+                    CONFIG_VALUE_FIELDS,
+                    "",
+                );
+                // Only one error is reported per value
+                Err(validation_errors
+                    .pop_first()
+                    .expect("Unknown fields should not be empty"))
+            }
             Self::Literal(value) => Ok(value),
-            Self::WrappedLiteral { value } => Ok(value),
-            Self::ReadFromFile { file: file_path } => {
+            Self::WrappedLiteral { value, .. } => Ok(value),
+            Self::ReadFromFile {
+                file: file_path, ..
+            } => {
                 if let Some(str) = data.get_file(&file_path) {
                     let value = str
                         .parse()
@@ -399,7 +1923,7 @@ where
                     Err(TestCaseValidationError::MissingExternalFile(file_path))
                 }
             }
-            Self::FetchFromEnv { env: var_name } => {
+            Self::FetchFromEnv { env: var_name, .. } => {
                 if let Some(str) = data.get_env_var(&var_name) {
                     let value = str
                         .parse()
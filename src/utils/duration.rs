@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+/// Parse a duration with a unit suffix, e.g. `200ms`, `1.5s` or `2m`
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+
+    let (value, unit_in_seconds) = if let Some(value) = input.strip_suffix("ms") {
+        (value, 0.001)
+    } else if let Some(value) = input.strip_suffix('s') {
+        (value, 1.0)
+    } else if let Some(value) = input.strip_suffix('m') {
+        (value, 60.0)
+    } else {
+        return None;
+    };
+
+    let value = value.trim().parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(value * unit_in_seconds).ok()
+}
+
+/// Format a duration using the same units, e.g. `12ms` or `1.50s`
+pub fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("200ms"), Some(Duration::from_millis(200)));
+        assert_eq!(parse_duration("5s"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("200"), None);
+        assert_eq!(parse_duration("ms"), None);
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_duration("1h"), None);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_micros(12_500)), "12ms");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.50s");
+        assert_eq!(format_duration(Duration::from_secs(75)), "75.00s");
+    }
+}
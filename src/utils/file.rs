@@ -1,4 +1,6 @@
 use relative_path::{RelativePath, RelativePathBuf};
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 /// Get parent directory of path
@@ -81,3 +83,52 @@ where
         }
     }
 }
+
+/// List all files in a directory (recursively)
+///
+/// The returned paths are relative to `dir` and sorted. Directories are not
+/// included in the output, which means that empty directories are ignored.
+/// Symbolic links to directories are followed, except if they point to one of
+/// the directories containing them, since that would loop forever.
+pub fn list_files<P>(dir: P) -> io::Result<Vec<RelativePathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let mut output = vec![];
+    let mut ancestors = vec![];
+    list_files_in(
+        dir.as_ref(),
+        RelativePath::new(""),
+        &mut ancestors,
+        &mut output,
+    )?;
+    output.sort();
+    Ok(output)
+}
+
+fn list_files_in(
+    dir: &Path,
+    prefix: &RelativePath,
+    ancestors: &mut Vec<PathBuf>, // Canonical paths of the directories being listed
+    output: &mut Vec<RelativePathBuf>,
+) -> io::Result<()> {
+    let canonical_dir = dir.canonicalize()?;
+    if ancestors.contains(&canonical_dir) {
+        return Ok(());
+    }
+    ancestors.push(canonical_dir);
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = prefix.join(entry.file_name().to_string_lossy().as_ref());
+
+        if entry.path().is_dir() {
+            list_files_in(&entry.path(), &path, ancestors, output)?;
+        } else {
+            output.push(path);
+        }
+    }
+
+    ancestors.pop();
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! Start programs from several threads at once, and wait for them to exit
+
+use std::io;
+use std::iter::Sum;
+use std::ops::Add;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::{PoisonError, RwLock};
+use std::time::Duration;
+
+// Programs inherit every descriptor that is not marked close-on-exec at the
+// moment they are started. Descriptors that can only be marked after they are
+// created (e.g. by `openpty`) are created while holding the write lock.
+static SPAWN_LOCK: RwLock<()> = RwLock::new(());
+
+/// Start a program. Programs can be started in parallel, but not while
+/// descriptors are being created by `without_spawning`.
+pub fn spawn(cmd: &mut Command) -> io::Result<Child> {
+    let _guard = SPAWN_LOCK.read().unwrap_or_else(PoisonError::into_inner);
+    cmd.spawn()
+}
+
+/// Run `f` while no programs are being started
+pub fn without_spawning<T>(f: impl FnOnce() -> T) -> T {
+    let _guard = SPAWN_LOCK.write().unwrap_or_else(PoisonError::into_inner);
+    f()
+}
+
+/// Start the program as the leader of a new process group, so that the
+/// processes it starts can be killed together with it
+pub fn new_process_group(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+/// Kill the program and the processes it started. On Unix, the program must
+/// be the leader of a process group (See `new_process_group`).
+pub fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
+/// The time a program spent running on the CPU
+#[derive(Clone, Copy, Default)]
+pub struct CpuTime {
+    pub user: Duration,
+    pub system: Duration,
+}
+
+impl Add for CpuTime {
+    type Output = CpuTime;
+
+    fn add(self, other: CpuTime) -> CpuTime {
+        CpuTime {
+            user: self.user + other.user,
+            system: self.system + other.system,
+        }
+    }
+}
+
+impl Sum for CpuTime {
+    fn sum<I: Iterator<Item = CpuTime>>(iter: I) -> CpuTime {
+        iter.fold(CpuTime::default(), Add::add)
+    }
+}
+
+/// Wait for the program to exit. The CPU time is only measured on Unix.
+#[cfg(unix)]
+pub fn wait(mut child: Child) -> io::Result<(ExitStatus, Option<CpuTime>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    // Like `Child::wait`, which does not return the resource usage, stdin is
+    // closed first so that the program does not wait for more input
+    drop(child.stdin.take());
+
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    while unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } == -1 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    let cpu_time = CpuTime {
+        user: duration_from_timeval(usage.ru_utime),
+        system: duration_from_timeval(usage.ru_stime),
+    };
+    Ok((ExitStatus::from_raw(status), Some(cpu_time)))
+}
+
+#[cfg(not(unix))]
+pub fn wait(mut child: Child) -> io::Result<(ExitStatus, Option<CpuTime>)> {
+    Ok((child.wait()?, None))
+}
+
+#[cfg(unix)]
+fn duration_from_timeval(value: libc::timeval) -> Duration {
+    Duration::from_secs(value.tv_sec as u64) + Duration::from_micros(value.tv_usec as u64)
+}
@@ -0,0 +1,144 @@
+use std::ops::Range;
+
+/// A position in a source file, used to show a snippet of the source
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceLocation {
+    pub line: usize,   // 1-based
+    pub column: usize, // 1-based, counted in characters
+    pub length: usize, // Number of characters to highlight, limited to the line
+    pub line_content: String,
+}
+
+impl SourceLocation {
+    pub fn from_span(source: &str, span: Range<usize>) -> Option<SourceLocation> {
+        let start = span.start.min(source.len());
+        if !source.is_char_boundary(start) {
+            return None;
+        }
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line_content = source[line_start..line_end].trim_end_matches('\r');
+
+        let line = source[..start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+
+        let end = span.end.clamp(start, line_start + line_content.len());
+        let length = source
+            .get(start..end)
+            .map_or(0, |x| x.chars().count())
+            .max(1);
+
+        Some(SourceLocation {
+            line,
+            column,
+            length,
+            line_content: line_content.to_owned(),
+        })
+    }
+
+    /// Create a location from a 0-based line and column
+    pub fn from_line_col(source: &str, line: usize, column: usize) -> Option<SourceLocation> {
+        let line_start = source
+            .split_inclusive('\n')
+            .take(line)
+            .map(|x| x.len())
+            .sum::<usize>();
+        let line_content = source[line_start..].lines().next().unwrap_or_default();
+        let offset = line_content
+            .char_indices()
+            .nth(column)
+            .map_or(line_content.len(), |(i, _)| i);
+
+        Self::from_span(source, line_start + offset..line_start + offset + 1)
+    }
+
+    /// Format the location like `rustc` does, e.g.
+    ///
+    /// ```text
+    ///  --> file.toml:2:1
+    ///   |
+    /// 2 | expected_stout = ""
+    ///   | ^^^^^^^^^^^^^^
+    /// ```
+    pub fn show_snippet(&self, source_file: &str) -> String {
+        let line_number = self.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let marker_offset = " ".repeat(self.column - 1);
+        let markers = "^".repeat(self.length);
+
+        format!(
+            "{gutter}--> {}:{}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}",
+            source_file,
+            self.line,
+            self.column,
+            line_number,
+            self.line_content,
+            marker_offset,
+            markers,
+            gutter = gutter,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_from_span() {
+        let source = "program = \"echo\"\nexpected_stout = \"\"\n";
+        let location = SourceLocation::from_span(source, 17..31);
+
+        assert_eq!(
+            location,
+            Some(SourceLocation {
+                line: 2,
+                column: 1,
+                length: 14,
+                line_content: String::from("expected_stout = \"\""),
+            })
+        );
+    }
+
+    #[test]
+    fn test_from_span_limited_to_line() {
+        let source = "a = \"\"\"\nfoo\n\"\"\"\n";
+        let location = SourceLocation::from_span(source, 4..15).unwrap();
+
+        assert_eq!(location.line, 1);
+        assert_eq!(location.column, 5);
+        assert_eq!(location.length, 3);
+    }
+
+    #[test]
+    fn test_from_line_col() {
+        let source = "a = 1\nb = ?\n";
+        let location = SourceLocation::from_line_col(source, 1, 4).unwrap();
+
+        assert_eq!(location.line, 2);
+        assert_eq!(location.column, 5);
+        assert_eq!(location.length, 1);
+    }
+
+    #[test]
+    fn test_show_snippet() {
+        let location = SourceLocation {
+            line: 2,
+            column: 1,
+            length: 14,
+            line_content: String::from("expected_stout = \"\""),
+        };
+
+        let expected = indoc! {r#"
+             --> test.toml:2:1
+              |
+            2 | expected_stout = ""
+              | ^^^^^^^^^^^^^^"#};
+
+        assert_eq!(location.show_snippet("test.toml"), expected);
+    }
+}
@@ -32,6 +32,85 @@ where
     output
 }
 
+/// Find the candidate that is most similar to `input`, if any are similar enough
+pub fn find_closest_match<'a>(input: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (input.chars().count() / 3).max(2);
+
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(input, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars = b.chars().collect::<Vec<_>>();
+    let mut previous_row = (0..=b_chars.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current_row = vec![i + 1];
+
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let substitution_cost = if a_char == *b_char { 0 } else { 1 };
+            current_row.push(
+                (previous_row[j] + substitution_cost)
+                    .min(previous_row[j + 1] + 1)
+                    .min(current_row[j] + 1),
+            );
+        }
+
+        previous_row = current_row;
+    }
+
+    previous_row[b_chars.len()]
+}
+
+/// Split a command line into words, using the quoting rules of a POSIX shell
+/// (without expansion of variables)
+pub fn split_command(line: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).push(chars.next()?),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    words.extend(word);
+    Some(words)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -52,4 +131,43 @@ mod tests {
 
         assert_eq!(indent_with("- ", "\n"), expected);
     }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("stdout", ""), 6);
+        assert_eq!(edit_distance("stout", "stdout"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_find_closest_match() {
+        let candidates = ["expected_stdout", "expected_stderr", "stdin"];
+
+        assert_eq!(
+            find_closest_match("expected_stout", &candidates),
+            Some("expected_stdout")
+        );
+        assert_eq!(find_closest_match("stdim", &candidates), Some("stdin"));
+        assert_eq!(find_closest_match("timeout", &candidates), None);
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(
+            split_command(r#"echo -n  'Hello world' "a \"b\"" c\ d"#),
+            Some(vec![
+                String::from("echo"),
+                String::from("-n"),
+                String::from("Hello world"),
+                String::from("a \"b\""),
+                String::from("c d"),
+            ])
+        );
+        assert_eq!(
+            split_command("echo ''"),
+            Some(vec![String::from("echo"), String::new()])
+        );
+        assert_eq!(split_command("echo 'unterminated"), None);
+    }
 }
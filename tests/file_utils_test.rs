@@ -1,4 +1,5 @@
 use aureum::utils::file;
+use relative_path::RelativePathBuf;
 use std::path::{Path, PathBuf};
 
 // SECTION: find_executable_path
@@ -124,3 +125,18 @@ fn test_display_path_with_relative_path() {
 
     assert_eq!(displayed_path, "sub_dir/example");
 }
+
+// SECTION: list_files
+
+#[test]
+fn test_list_files_recursively() {
+    let files = file::list_files("tests/file_utils").unwrap();
+
+    assert_eq!(
+        files,
+        vec![
+            RelativePathBuf::from("hello_world.sh"),
+            RelativePathBuf::from("sub_dir/hello_sub_dir.sh"),
+        ]
+    );
+}